@@ -0,0 +1,43 @@
+//! Wall-clock cost of simulation steps and potential updates in crowded
+//! scenes, run with `cargo bench`.
+
+use macroquad::math::Vec2;
+use point_charge_simulation::charges::PointCharge;
+use point_charge_simulation::simulation::Simulation;
+use std::time::Instant;
+
+const STEPS: u32 = 20;
+
+/// A square lattice of `count` alternating charges, 40 pixels apart, so that none touch.
+fn lattice(count: usize) -> Simulation {
+    let mut simulation = Simulation::new(1600, 1000);
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    let columns = (count as f32).sqrt().ceil() as usize;
+    for i in 0..count {
+        let center = 40.0 * Vec2::new((i % columns) as f32, (i / columns) as f32);
+        let q = if (i % columns + i / columns).is_multiple_of(2) { 1.0 } else { -1.0 } * PointCharge::DEFAULT_CHARGE;
+        simulation.spawn_charge(center, q, false);
+    }
+    simulation
+}
+
+fn main() {
+    for count in [1000, 2000, 4000] {
+        let mut simulation = lattice(count);
+        let start = Instant::now();
+        for _ in 0..STEPS {
+            simulation.step(simulation.timestep.substep());
+        }
+        let per_step = start.elapsed() / STEPS;
+
+        let mut simulation = lattice(count);
+        let start = Instant::now();
+        simulation.advance(1.0 / 60.0);
+        let per_frame = start.elapsed();
+        simulation.update_potential();
+        let start = Instant::now();
+        simulation.update_potential();
+        let static_potential = start.elapsed();
+        println!("{count} charges: {per_step:?} per step, {per_frame:?} per 1/60 s frame, {static_potential:?} per potential update of a static scene");
+    }
+}
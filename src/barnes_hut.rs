@@ -0,0 +1,181 @@
+use crate::charges::coulomb_force;
+use crate::units::Units;
+use macroquad::math::Vec2;
+use std::ops::Range;
+
+/// Total charge of one sign inside a node, as if it all lay at `center`.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+struct Monopole {
+    q: f32,
+    /// Charge-weighted mean position.
+    center: Vec2,
+}
+
+impl Monopole {
+    fn add(&mut self, center: Vec2, q: f32) {
+        let total = self.q + q;
+        self.center = (self.center * self.q + center * q) / total;
+        self.q = total;
+    }
+}
+
+#[derive(Clone, Debug)]
+struct Node {
+    center: Vec2,
+    half_size: f32,
+    /// Positive and negative charges are summarized separately, as a single
+    /// monopole would sit between them and misrepresent neutral clusters.
+    positive: Monopole,
+    negative: Monopole,
+    /// Indices in `QuadTree::order` of the sources inside the node.
+    sources: Range<usize>,
+    /// Indices in `QuadTree::nodes` of the non-empty quadrants, none for leaves.
+    children: Vec<usize>,
+}
+
+impl Node {
+    fn contains(&self, point: Vec2) -> bool {
+        (point - self.center).abs().max_element() <= self.half_size
+    }
+
+    /// Whether the node is far enough from `point`, seen under an angle smaller
+    /// than `opening_angle`, to be replaced by its monopoles.
+    fn is_far_from(&self, point: Vec2, opening_angle: f32) -> bool {
+        let size = 2.0 * self.half_size;
+        !self.contains(point) && [self.positive, self.negative].iter()
+            .filter(|monopole| monopole.q != 0.0)
+            .all(|monopole| size < opening_angle * point.distance(monopole.center))
+    }
+}
+
+/// Barnes–Hut quadtree over point charges, approximating the field and the
+/// potential they generate in O(log N) per evaluation instead of O(N).
+///
+/// Nodes seen under an angle smaller than the opening angle are replaced by
+/// their monopoles; an opening angle of zero gives the direct sum.
+#[derive(Clone, Debug)]
+pub struct QuadTree {
+    nodes: Vec<Node>,
+    /// Position and charge of every source, in the order given.
+    sources: Vec<(Vec2, f32)>,
+    /// Indices of the charged sources, grouped by node.
+    order: Vec<usize>,
+    pub opening_angle: f32,
+}
+
+impl QuadTree {
+    pub const DEFAULT_OPENING_ANGLE: f32 = 0.5;
+    // Sources below which a node is not split, as summing them directly is cheaper
+    const LEAF_CAPACITY: usize = 4;
+    // Guards against splitting forever around coincident sources
+    const MAX_DEPTH: usize = 32;
+
+    /// Builds the tree over `sources`, given as position and charge. Neutral and
+    /// non-finite sources are left out, but still count for the indices.
+    #[must_use]
+    pub fn new(sources: impl IntoIterator<Item = (Vec2, f32)>, opening_angle: f32) -> Self {
+        let sources: Vec<(Vec2, f32)> = sources.into_iter().collect();
+        let order: Vec<usize> = (0..sources.len())
+            .filter(|&i| sources[i].1 != 0.0 && sources[i].0.is_finite())
+            .collect();
+        let (min, max) = order.iter()
+            .map(|&i| sources[i].0)
+            .fold((Vec2::INFINITY, Vec2::NEG_INFINITY), |(min, max), point| (min.min(point), max.max(point)));
+        let mut tree = QuadTree { nodes: vec![], sources, order, opening_angle };
+        if !tree.order.is_empty() {
+            let half_size = (max - min).max_element() / 2.0;
+            // Padded so that rounding never leaves a source outside the root
+            tree.build(0..tree.order.len(), (min + max) / 2.0, half_size * 1.001 + f32::EPSILON, 0);
+        }
+        tree
+    }
+
+    /// Adds the node holding `order[sources]` and its descendants, returning its index.
+    fn build(&mut self, sources: Range<usize>, center: Vec2, half_size: f32, depth: usize) -> usize {
+        let mut node = Node {
+            center,
+            half_size,
+            positive: Monopole::default(),
+            negative: Monopole::default(),
+            sources: sources.clone(),
+            children: vec![],
+        };
+        for &i in &self.order[sources.clone()] {
+            let (point, q) = self.sources[i];
+            if q > 0.0 { node.positive.add(point, q) } else { node.negative.add(point, q) }
+        }
+        let index = self.nodes.len();
+        self.nodes.push(node);
+        if sources.len() <= Self::LEAF_CAPACITY || depth >= Self::MAX_DEPTH {
+            return index;
+        }
+
+        let quadrant = |point: Vec2| usize::from(point.x >= center.x) + 2 * usize::from(point.y >= center.y);
+        let points = &self.sources;
+        self.order[sources.clone()].sort_unstable_by_key(|&i| quadrant(points[i].0));
+        let mut start = sources.start;
+        let mut children = vec![];
+        for q in 0..4 {
+            let end = start + self.order[start..sources.end].iter().take_while(|&&i| quadrant(self.sources[i].0) == q).count();
+            if end > start {
+                let offset = Vec2::new(if q % 2 == 0 { -1.0 } else { 1.0 }, if q < 2 { -1.0 } else { 1.0 });
+                children.push(self.build(start..end, center + offset * half_size / 2.0, half_size / 2.0, depth + 1));
+            }
+            start = end;
+        }
+        self.nodes[index].children = children;
+        index
+    }
+
+    /// Visits the monopoles and sources making up the field at `point`, the
+    /// latter with their index, approximating far nodes.
+    fn visit(&self, point: Vec2, mut visit_monopole: impl FnMut(Vec2, f32, Option<usize>)) {
+        if self.nodes.is_empty() {
+            return;
+        }
+        let mut stack = vec![0];
+        while let Some(index) = stack.pop() {
+            let node = &self.nodes[index];
+            if node.children.is_empty() {
+                for &i in &self.order[node.sources.clone()] {
+                    let (center, q) = self.sources[i];
+                    visit_monopole(center, q, Some(i));
+                }
+            } else if node.is_far_from(point, self.opening_angle) {
+                for monopole in [node.positive, node.negative] {
+                    if monopole.q != 0.0 {
+                        visit_monopole(monopole.center, monopole.q, None);
+                    }
+                }
+            } else {
+                stack.extend(&node.children);
+            }
+        }
+    }
+
+    /// Electric field at `point`, leaving out the source at index `exclude`, as
+    /// when computing the force a charge feels from all the others.
+    #[must_use]
+    pub fn field_at(&self, point: Vec2, units: &Units, exclude: Option<usize>) -> Vec2 {
+        let mut field = Vec2::ZERO;
+        self.visit(point, |center, q, index| {
+            if index.is_none() || index != exclude {
+                field += coulomb_force(point, 1.0, center, q, units);
+            }
+        });
+        field
+    }
+
+    /// Potential at `point`, leaving out the source at index `exclude` and seeing
+    /// sources closer than `min_distance` pixels as if at that distance.
+    #[must_use]
+    pub fn potential_at(&self, point: Vec2, units: &Units, exclude: Option<usize>, min_distance: f32) -> f32 {
+        let mut potential = 0.0;
+        self.visit(point, |center, q, index| {
+            if index.is_none() || index != exclude {
+                potential += units.potential(q, center.distance(point).max(min_distance));
+            }
+        });
+        potential
+    }
+}
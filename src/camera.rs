@@ -0,0 +1,122 @@
+use crate::charges::PointCharge;
+use macroquad::camera::Camera2D;
+use macroquad::math::{Rect, Vec2};
+
+/// View over the unbounded world: the world point shown at the middle of the
+/// window and the magnification, in screen pixels per world pixel.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Camera {
+    pub center: Vec2,
+    pub zoom: f32,
+    /// Size of the window, in screen pixels.
+    pub viewport: Vec2,
+}
+
+impl Camera {
+    pub const MIN_ZOOM: f32 = 0.05;
+    pub const MAX_ZOOM: f32 = 20.0;
+    // Room left around the charges by `fit`, in screen pixels
+    const FIT_MARGIN: f32 = 60.0;
+
+    /// A camera showing world pixels one-to-one with screen pixels, so that the
+    /// world initially matches the window.
+    #[must_use]
+    pub fn new(viewport: Vec2) -> Self {
+        Camera { center: viewport / 2.0, zoom: 1.0, viewport }
+    }
+
+    #[must_use]
+    pub fn world_to_screen(&self, point: Vec2) -> Vec2 {
+        (point - self.center) * self.zoom + self.viewport / 2.0
+    }
+
+    #[must_use]
+    pub fn screen_to_world(&self, point: Vec2) -> Vec2 {
+        (point - self.viewport / 2.0) / self.zoom + self.center
+    }
+
+    /// The part of the world covered by the window.
+    #[must_use]
+    pub fn visible_region(&self) -> Rect {
+        let size = self.viewport / self.zoom;
+        Rect::new(self.center.x - size.x / 2.0, self.center.y - size.y / 2.0, size.x, size.y)
+    }
+
+    /// Multiplies the zoom by `factor`, keeping the world point under `screen_point` in place.
+    pub fn zoom_at(&mut self, screen_point: Vec2, factor: f32) {
+        let anchor = self.screen_to_world(screen_point);
+        self.zoom = (self.zoom * factor).clamp(Self::MIN_ZOOM, Self::MAX_ZOOM);
+        self.center = anchor - (screen_point - self.viewport / 2.0) / self.zoom;
+    }
+
+    /// Moves the world by `screen_offset`, as when dragging it.
+    pub fn pan(&mut self, screen_offset: Vec2) {
+        self.center -= screen_offset / self.zoom;
+    }
+
+    /// Frames every charge, returning false and leaving the view untouched if there is none.
+    pub fn fit(&mut self, charges: &[PointCharge]) -> bool {
+        let Some(bounds) = charges.iter()
+            .map(|charge| {
+                let radius = charge.drawing_circle.radius;
+                Rect::new(charge.center.x - radius, charge.center.y - radius, 2.0 * radius, 2.0 * radius)
+            })
+            .reduce(|bounds, circle| bounds.combine_with(circle)) else {
+            return false;
+        };
+        let available = (self.viewport - 2.0 * Self::FIT_MARGIN).max(Vec2::ONE);
+        let scale = available / bounds.size().max(Vec2::ONE);
+        self.center = bounds.center();
+        self.zoom = scale.min_element().clamp(Self::MIN_ZOOM, Self::MAX_ZOOM);
+        true
+    }
+
+    /// The equivalent macroquad camera, for drawing in world coordinates.
+    #[must_use]
+    pub fn to_camera_2d(&self) -> Camera2D {
+        Camera2D {
+            target: self.center,
+            zoom: 2.0 * self.zoom / self.viewport,
+            ..Default::default()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn camera() -> Camera {
+        Camera { center: Vec2::new(120.0, -40.0), zoom: 2.5, viewport: Vec2::new(800.0, 600.0) }
+    }
+
+    #[test]
+    fn screen_and_world_points_round_trip() {
+        let camera = camera();
+        for point in [Vec2::ZERO, Vec2::new(400.0, 300.0), Vec2::new(-35.5, 812.25)] {
+            assert!((camera.world_to_screen(camera.screen_to_world(point)) - point).length() <= 1e-3);
+            assert!((camera.screen_to_world(camera.world_to_screen(point)) - point).length() <= 1e-3);
+        }
+        assert_eq!(camera.world_to_screen(camera.center), camera.viewport / 2.0);
+    }
+
+    #[test]
+    fn visible_region_spans_the_window() {
+        let camera = camera();
+        let region = camera.visible_region();
+        assert_eq!(camera.world_to_screen(region.point()), Vec2::ZERO);
+        assert_eq!(camera.world_to_screen(region.point() + region.size()), camera.viewport);
+    }
+
+    #[test]
+    fn zooming_keeps_the_point_under_the_cursor() {
+        let mut camera = camera();
+        let cursor = Vec2::new(650.0, 120.0);
+        let anchor = camera.screen_to_world(cursor);
+        camera.zoom_at(cursor, 1.7);
+        assert!((camera.world_to_screen(anchor) - cursor).length() <= 1e-3);
+
+        camera.zoom_at(cursor, 1e6);
+        assert_eq!(camera.zoom, Camera::MAX_ZOOM);
+    }
+}
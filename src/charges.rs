@@ -1,14 +1,15 @@
 use self::Sign::{Negative, Positive};
 use crate::charges::Sign::Neutral;
 use crate::geometry::{ChargeCircle, FieldArrow, ForceArrow};
+use crate::integrators::PhaseState;
+use crate::units::Units;
 use crate::Drawable;
-use macroquad::color::{Color, BLUE, GREEN, LIGHTGRAY, RED};
-use macroquad::color_u8;
-use macroquad::math::{cartesian_to_polar, polar_to_cartesian, Rect, Vec2};
+use macroquad::color::{Color, BLACK, BLUE, GREEN, LIGHTGRAY, RED, WHITE};
+use macroquad::math::{Rect, Vec2};
 use std::fmt;
 
 
-#[derive(PartialEq, Debug)]
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
 pub enum Sign {
     Neutral = 0,
     Positive = 1,
@@ -16,6 +17,19 @@ pub enum Sign {
 
 }
 
+impl Sign {
+    #[must_use]
+    pub fn from_charge(q: f32) -> Self {
+        if q > 0.0 {
+            Positive
+        } else if q < 0.0 {
+            Negative
+        } else {
+            Neutral
+        }
+    }
+}
+
 impl fmt::Display for Sign {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         if *self == Positive {
@@ -29,43 +43,38 @@ impl fmt::Display for Sign {
     }
 }
 
-const K: f32 = 8.99 * 10e9;
-const FORCE_SCALING_FACTOR: f32 = 50e5;
 
 
 
-#[must_use] pub fn calculate_potential(point: &Vec2, charges: &Vec<PointCharge>) -> f32 {
+#[must_use] pub fn calculate_potential(point: &Vec2, charges: &[PointCharge], units: &Units) -> f32 {
     let mut potential: f32 = 0.0;
     for charge in charges {
         let distance = point.distance(charge.center);
-        potential += K * charge.q / distance;
+        potential += units.potential(charge.q, distance);
 
     }
 
     potential
 
 }
-pub fn color_based_on_potential(potential: f32, max_potential: f32) -> Color {
-    let mut color_intensity: u8 = 0;
-    if potential.abs() > max_potential {
-        color_intensity = 255;
-    } else if max_potential > 0.1 {
-        color_intensity = (f32::round(2.0* ((potential.abs() * 255.0) / max_potential)) as u16).min(255) as u8;
-    }
-    // dbg!(color_intensity);
-    let color: Color;
-    if potential > 1.0 {
-        color = color_u8!(color_intensity, 0, 0, 255);
-    } else if potential < -1.0 {
-        color = color_u8!(0, 0, color_intensity, 255);
-    } else {
-        color = color_u8!(color_intensity, color_intensity, color_intensity, 255);
-    }
-    return color;
+fn mix_colors(from: Color, to: Color, amount: f32) -> Color {
+    Color::new(
+        from.r + (to.r - from.r) * amount,
+        from.g + (to.g - from.g) * amount,
+        from.b + (to.b - from.b) * amount,
+        from.a,
+    )
 }
 
+/// Coulomb force exerted on the charge `q_on` lying at `on` by the charge
+/// `q_from` lying at `from`.
+#[must_use]
+pub fn coulomb_force(on: Vec2, q_on: f32, from: Vec2, q_from: f32, units: &Units) -> Vec2 {
+    let delta = on - from;
+    units.coulomb_force(q_on, q_from, delta.length_squared()) * delta.normalize_or_zero()
+}
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct PointCharge {
     pub id: usize,
     pub center: Vec2,
@@ -73,10 +82,11 @@ pub struct PointCharge {
     pub sign: Sign,
     pub is_fixed: bool,
     pub is_selected: bool,
-    pub is_colliding: bool,
 
     pub m: f32,
     q: f32,
+    /// Color overriding the one derived from the charge, e.g. that of a species.
+    color: Option<Color>,
     forces: Vec<Vec2>,
     net_force: Vec2,
     max_force_magnitude: f32,
@@ -89,31 +99,28 @@ pub struct PointCharge {
 
 impl PointCharge {
     pub const DEFAULT_RADIUS: f32 = 16.0;
-    const DEFAULT_CHARGE: f32 = 2e-8;
-    const DEFAULT_MASS: f32 = 1.67 * 10e-3;
+    pub const DEFAULT_CHARGE: f32 = 2e-8;
+    pub const DEFAULT_MASS: f32 = 1.67 * 10e-3;
     const NULL_VECTOR: Vec2 = Vec2::ZERO;
     const ENCLOSING_SQUARE_PADDING: f32 = Self::DEFAULT_RADIUS * 2.5;
-    const FRICTION: f32 = 0.95;
-
-    pub fn new_positive_charge(id: usize, center: Vec2, is_fixed: bool) -> Self {
-        let drawing_circle = ChargeCircle::new(
-            center,
-            PointCharge::DEFAULT_RADIUS,
-            RED,
-            Some(Positive),
-            is_fixed);
+    const MIN_RADIUS_SCALE: f32 = 0.6;
+    const MAX_RADIUS_SCALE: f32 = 2.0;
 
+    /// Creates a charge carrying an arbitrary signed charge `q`: its sign, size
+    /// and color are derived from the value.
+    #[must_use]
+    pub fn new(id: usize, center: Vec2, q: f32, is_fixed: bool) -> Self {
         PointCharge {
             id,
             center,
-            drawing_circle,
-            sign: Positive,
+            drawing_circle: Self::drawing_circle_for(center, q, is_fixed, None),
+            sign: Sign::from_charge(q),
             is_fixed,
             is_selected: false,
-            is_colliding: false,
 
             m: Self::DEFAULT_MASS,
-            q: Self::DEFAULT_CHARGE,
+            q,
+            color: None,
             forces: vec![],
             net_force: Self::NULL_VECTOR,
             max_force_magnitude: 0.0,
@@ -121,91 +128,94 @@ impl PointCharge {
             velocity: Self::NULL_VECTOR
 
         }
+    }
 
-
+    #[must_use]
+    pub fn new_positive_charge(id: usize, center: Vec2, is_fixed: bool) -> Self {
+        Self::new(id, center, Self::DEFAULT_CHARGE, is_fixed)
     }
 
+    #[must_use]
     pub fn new_negative_charge(id: usize, center: Vec2, is_fixed: bool) -> Self {
-        let drawing_circle = ChargeCircle::new(
-            center,
-            PointCharge::DEFAULT_RADIUS,
-            BLUE,
-        Some(Negative),
-            is_fixed);
-
-        PointCharge {
-            id: id,
-            center: center,
-            drawing_circle: drawing_circle,
-            sign: Negative,
-            is_fixed: is_fixed,
-            is_selected: false,
-            is_colliding: false,
-
-            m: Self::DEFAULT_MASS,
-            q: -Self::DEFAULT_CHARGE,
-            forces: vec![],
-            net_force: Self::NULL_VECTOR,
-            max_force_magnitude: 0.0,
-            acceleration: Self::NULL_VECTOR,
-            velocity: Self::NULL_VECTOR
-
-        }
-
+        Self::new(id, center, -Self::DEFAULT_CHARGE, is_fixed)
+    }
 
+    #[must_use]
+    pub fn q(&self) -> f32 {
+        self.q
+    }
 
+    /// Changes the charge carried, updating sign and appearance accordingly.
+    pub fn set_charge(&mut self, q: f32) {
+        self.q = q;
+        self.sign = Sign::from_charge(q);
+        self.drawing_circle = Self::drawing_circle_for(self.center, q, self.is_fixed, self.color);
     }
 
+    #[must_use]
+    pub fn color(&self) -> Option<Color> {
+        self.color
+    }
 
-    // Add this constructor to PointCharge impl
-    pub fn new_neutral_charge_from_merge(id: usize, center: Vec2, is_fixed: bool) -> Self {
-        let drawing_circle = ChargeCircle::new(
-            center,
-            PointCharge::DEFAULT_RADIUS,
-            LIGHTGRAY, // Gray for neutral
-            Some(Neutral),
-        is_fixed); // No sign for neutral charge
+    /// Overrides the color derived from the charge, or goes back to it with `None`.
+    /// The color is kept when the charge changes.
+    pub fn set_color(&mut self, color: Option<Color>) {
+        self.color = color;
+        self.drawing_circle = Self::drawing_circle_for(self.center, self.q, self.is_fixed, color);
+    }
 
-        PointCharge {
-            id,
-            center,
-            drawing_circle,
-            sign: Sign::Neutral,
-            is_fixed,
-            is_selected: false,
-            is_colliding: false,
+    /// Reverses the sign of the charge, keeping its magnitude.
+    pub fn flip_sign(&mut self) {
+        self.set_charge(-self.q);
+    }
 
-            m: 2.0*Self::DEFAULT_MASS,
-            q: 0.0, // Neutral has zero charge
-            forces: vec![],
-            net_force: Self::NULL_VECTOR,
-            max_force_magnitude: 0.0,
-            acceleration: Self::NULL_VECTOR,
-            velocity: Self::NULL_VECTOR
+    /// Pins the charge in place or releases it; pinned charges lose their velocity.
+    pub fn set_fixed(&mut self, is_fixed: bool) {
+        self.is_fixed = is_fixed;
+        self.drawing_circle.set_fixed(is_fixed);
+        if is_fixed {
+            self.velocity = Self::NULL_VECTOR;
         }
     }
 
-    pub fn force_with(&mut self, point_charge: &PointCharge) -> Vec2 {
-        let distance_squared = self.center.distance_squared(point_charge.center);
-        let magnitude =  FORCE_SCALING_FACTOR * K * self.q * point_charge.q / distance_squared;
-        let delta = Vec2::new(self.center.x - point_charge.center.x, self.center.y - point_charge.center.y);
-        let direction = delta.y.atan2(delta.x);
+    /// Circle whose radius grows with the square root of the charge magnitude,
+    /// and whose color, unless given, gets paler for weak charges and deeper for
+    /// strong ones.
+    fn drawing_circle_for(center: Vec2, q: f32, is_fixed: bool, color: Option<Color>) -> ChargeCircle {
+        let sign = Sign::from_charge(q);
+        let magnitude_ratio = q.abs() / Self::DEFAULT_CHARGE;
+
+        let (radius, derived_color) = match sign {
+            Neutral => (Self::DEFAULT_RADIUS, LIGHTGRAY),
+            Positive | Negative => {
+                let radius = Self::DEFAULT_RADIUS * magnitude_ratio.sqrt().clamp(Self::MIN_RADIUS_SCALE, Self::MAX_RADIUS_SCALE);
+                let base_color = if sign == Positive { RED } else { BLUE };
+                // -1.0 for a quarter of the default charge or less, 1.0 for four times or more
+                let intensity = (magnitude_ratio.log2() / 2.0).clamp(-1.0, 1.0);
+                let color = if intensity < 0.0 {
+                    mix_colors(base_color, WHITE, -intensity * 0.6)
+                } else {
+                    mix_colors(base_color, BLACK, intensity * 0.4)
+                };
+                (radius, color)
+            }
+        };
 
-        let force = Vec2::new(magnitude, direction);
-        self.forces.push(force);
-        force
+        ChargeCircle::new(center, radius, color.unwrap_or(derived_color), Some(sign), is_fixed)
     }
 
-    pub fn calculate_net_force(&mut self) {
-        self.net_force = cartesian_to_polar(self.forces.iter()
-            .map(|force| polar_to_cartesian(force.x, force.y))
-            .sum());
+    /// Sets the forces exerted by each other charge, which may be left empty
+    /// when only their sum is known, and the net force.
+    pub fn set_forces(&mut self, forces: Vec<Vec2>, net_force: Vec2) {
+        self.forces = forces;
+        self.net_force = net_force;
     }
 
     pub fn calculate_max_force(&mut self) {
 
-       self.max_force_magnitude = self.forces.iter().max_by(|force1, force2| force1.x.partial_cmp(&force2.x).expect("Failed to compare!") ).unwrap_or(&Vec2::INFINITY).x;
-        self.max_force_magnitude = self.max_force_magnitude.max((self.net_force).x);
+        self.max_force_magnitude = self.forces.iter()
+            .map(|force| force.length())
+            .fold(self.net_force.length(), f32::max);
 
 
         // dbg!(&self.forces, self.net_force, self.max_force_magnitude);
@@ -217,140 +227,64 @@ impl PointCharge {
         self.net_force = Self::NULL_VECTOR;
     }
 
-    pub fn calculate_acceleration(&mut self) {
+    /// Acceleration in pixels per second squared.
+    pub fn calculate_acceleration(&mut self, units: &Units) {
 
         if self.is_fixed {
             //dbg!(&self.forces);
             // dbg!(self.net_force);
             self.acceleration = Self::NULL_VECTOR;
         } else {
-            self.acceleration = Vec2::new(self.net_force.x / self.m, self.net_force.y);
+            self.acceleration = units.metres_to_pixels(1.0) * self.net_force / self.m;
         }
     }
 
-    pub fn calculate_velocity(&mut self) {
-        if self.is_fixed {
-            self.velocity = Self::NULL_VECTOR;
-        } else {
-            let mut new_speed = self.acceleration.x + self.velocity.x * Self::FRICTION;
-            if new_speed <= 0.005 || self.is_colliding {
-                new_speed = 0.0;
-            }
-            self.velocity = Vec2::new(new_speed, self.net_force.y);
-        }
+    #[must_use]
+    pub fn acceleration(&self) -> Vec2 {
+        self.acceleration
     }
 
-
-    pub fn movement(&mut self, delta: f32) {
-        if self.is_colliding {
-            return;
-        }
-        let cartesian_velocity = polar_to_cartesian(self.velocity.x, self.velocity.y);
-        self.center += cartesian_velocity * delta;
-        self.drawing_circle.center += cartesian_velocity * delta;
-        // dbg!(self.center, cartesian_velocity);
-    }
-
-
-
-    pub fn check_collision_with(&mut self, point_charge: &mut PointCharge) {
-        // Reset collision state
-        self.is_colliding = false;
-
-        // Get distance between charges
-        let distance_squared = self.center.distance_squared(point_charge.center);
-        let min_distance = (self.drawing_circle.radius + point_charge.drawing_circle.radius);
-
-        // Check if colliding
-        if distance_squared < min_distance.powi(2) {
-            self.is_colliding = true;
-            point_charge.is_colliding = true;
-
-            // Only apply collision response if at least one charge is not fixed
-            if !self.is_fixed || !point_charge.is_fixed {
-                let distance = distance_squared.sqrt();
-                if distance > 0.0 {
-                    let overlap = min_distance - distance;
-                    let direction = (self.center - point_charge.center).normalize();
-
-                    // Calculate how much each charge should move (based on fixed status)
-                    let self_factor = if self.is_fixed { 0.0 } else { 1.0 };
-                    let other_factor = if point_charge.is_fixed { 0.0 } else { 1.0 };
-
-                    // Normalize movement factors
-                    let total_factor = self_factor + other_factor;
-                    let (self_factor, other_factor) = if total_factor > 0.0 {
-                        (self_factor / total_factor, other_factor / total_factor)
-                    } else {
-                        (0.0, 0.0)
-                    };
-
-                    // Apply position correction with strength proportional to overlap
-                    let correction_factor = 1.5; // Stronger correction for low FPS
-                    self.center += direction * overlap * self_factor * correction_factor;
-                    point_charge.center -= direction * overlap * other_factor * correction_factor;
-
-                    // Update drawing positions
-                    self.drawing_circle.center = self.center;
-                    point_charge.drawing_circle.center = point_charge.center;
-
-                    // Apply velocity correction (bounce effect)
-                    // Apply velocity correction (bounce effect)
-                    if !self.is_fixed && !point_charge.is_fixed {
-                        // Calculate relative velocity
-                        let rel_velocity = self.velocity - point_charge.velocity;
-                        let vel_along_normal = rel_velocity.dot(direction);
-
-                        // Only apply bounce if objects are moving toward each other
-                        if vel_along_normal < 0.0 {
-                            let restitution = 0.5; // Bounciness factor
-
-                            // Calculate impulse with mass consideration
-                            let impulse_scalar = -(1.0 + restitution) * vel_along_normal /
-                                (1.0/self.m + 1.0/point_charge.m);
-
-                            // Apply velocity changes proportional to inverse mass
-                            self.velocity += direction * impulse_scalar / self.m;
-                            point_charge.velocity -= direction * impulse_scalar / point_charge.m;
-                        }
-                    }
-                }
-            }
-        }
+    #[must_use]
+    pub fn net_force(&self) -> Vec2 {
+        self.net_force
     }
 
-
-    pub fn enclosing_square(&self) -> Rect {
-        self.drawing_circle.enclosing_square(Self::ENCLOSING_SQUARE_PADDING)
+    pub fn set_center(&mut self, center: Vec2) {
+        self.center = center;
+        self.drawing_circle.center = center;
     }
 
+    #[must_use]
+    pub fn phase_state(&self) -> PhaseState {
+        PhaseState { position: self.center, velocity: self.velocity }
+    }
 
-    pub fn potential_contribution_at(&self, point: &Vec2) -> f32 {
-        let distance = self.center.distance(*point);
-        return K * self.q / distance;
+    pub fn set_phase_state(&mut self, state: PhaseState) {
+        self.set_center(state.position);
+        self.velocity = if self.is_fixed { Self::NULL_VECTOR } else { state.velocity };
     }
 
-    // Add this method to check for opposite charges
-    pub fn should_merge_with(&self, other: &PointCharge) -> bool {
 
-        // I don't know if I should keep the commented code or not
-        /*if (self.is_fixed || other.is_fixed) {
-            return false;
-        }*/
 
+    pub fn enclosing_square(&self) -> Rect {
+        self.drawing_circle.enclosing_square(Self::ENCLOSING_SQUARE_PADDING)
+    }
+
+    #[must_use]
+    pub fn has_opposite_sign_to(&self, other: &PointCharge) -> bool {
         (self.sign == Positive && other.sign == Negative) ||
             (self.sign == Negative && other.sign == Positive)
     }
 
     pub fn draw_forces(&self ) {
         for force in &self.forces {
-            ForceArrow::new(self.center, force.x, self.max_force_magnitude, force.y, LIGHTGRAY ).draw();
+            ForceArrow::new(self.center, *force, self.max_force_magnitude, LIGHTGRAY).draw();
         }
 
     }
 
     pub fn draw_net_force(&self) {
-        ForceArrow::new(self.center, self.net_force.x, self.max_force_magnitude, self.net_force.y, GREEN).draw();
+        ForceArrow::new(self.center, self.net_force, self.max_force_magnitude, GREEN).draw();
     }
 
     pub fn draw(&self) {
@@ -373,8 +307,6 @@ pub struct TestCharge {
     pub center: Vec2,
     pub drawing_arrow: FieldArrow,
     pub is_hidden: bool,
-    q: f32,
-    forces: Vec<Vec2>,
     pub net_force: Vec2,
     max_force_magnitude: f32,
     potential: f32
@@ -386,10 +318,8 @@ impl TestCharge {
     pub fn new(center: Vec2) -> Self {
         TestCharge {
             center,
-            drawing_arrow: FieldArrow::new(center, 0.0, 0.0, 0.0, 0.0),
+            drawing_arrow: FieldArrow::new(center, Vec2::ZERO, 0.0, 0.0),
             is_hidden: false,
-            q: 1.0,
-            forces: vec![],
             net_force: Self::NULL_VECTOR,
             max_force_magnitude: 0.0,
             potential: 0.0,
@@ -397,23 +327,6 @@ impl TestCharge {
 
     }
 
-    pub fn force_with(&mut self, point_charge: &PointCharge) -> Vec2 {
-        let distance_squared = self.center.distance_squared(point_charge.center);
-        let magnitude =  FORCE_SCALING_FACTOR * K * point_charge.q / distance_squared;
-        let delta = Vec2::new(self.center.x - point_charge.center.x, self.center.y - point_charge.center.y);
-        let direction = delta.y.atan2(delta.x);
-
-        let force = Vec2::new(magnitude, direction);
-        self.forces.push(force);
-        force
-    }
-
-    pub fn calculate_net_force(&mut self) {
-        self.net_force = cartesian_to_polar(self.forces.iter()
-            .map(|force| polar_to_cartesian(force.x, force.y))
-            .sum());
-    }
-
     pub fn set_max_force(&mut self, max_magnitude: f32) {
 
         // self.max_force_magnitude = self.forces.iter().max_by(|force1, force2| force1.x.partial_cmp(&force2.x).expect("Failed to compare!") ).unwrap_or(&Vec2::INFINITY).x;
@@ -425,7 +338,6 @@ impl TestCharge {
     }
 
     pub fn clear_forces(&mut self) {
-        self.forces.clear();
         self.net_force = Self::NULL_VECTOR;
         self.max_force_magnitude = 0.0;
         self.potential = 0.0;
@@ -433,11 +345,57 @@ impl TestCharge {
 
 
     pub fn update_arrow(&mut self) {
-        self.drawing_arrow.update((self.net_force.x), self.max_force_magnitude, self.net_force.y, self.potential);
+        self.drawing_arrow.update(self.net_force, self.max_force_magnitude, self.potential);
     }
     pub fn draw(&self) {
-        if (!self.is_hidden) {
+        if !self.is_hidden {
             self.drawing_arrow.draw();
         }
     }
+
+    /// Draws the arrow at `screen_point`, so that its size does not depend on the zoom.
+    pub fn draw_at(&self, screen_point: Vec2) {
+        if !self.is_hidden {
+            self.drawing_arrow.draw_at(screen_point);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const Q: f32 = PointCharge::DEFAULT_CHARGE;
+
+    fn circle(q: f32) -> ChargeCircle {
+        PointCharge::new(0, Vec2::ZERO, q, false).drawing_circle
+    }
+
+    #[test]
+    fn radius_grows_with_the_square_root_of_the_charge_within_bounds() {
+        assert_eq!(circle(Q).radius, PointCharge::DEFAULT_RADIUS);
+        assert_eq!(circle(-Q).radius, PointCharge::DEFAULT_RADIUS);
+        assert!((circle(2.0 * Q).radius - PointCharge::DEFAULT_RADIUS * 2.0_f32.sqrt()).abs() < 1e-4);
+        assert_eq!(circle(100.0 * Q).radius, PointCharge::DEFAULT_RADIUS * PointCharge::MAX_RADIUS_SCALE);
+        assert_eq!(circle(0.01 * Q).radius, PointCharge::DEFAULT_RADIUS * PointCharge::MIN_RADIUS_SCALE);
+        assert_eq!(circle(0.0).radius, PointCharge::DEFAULT_RADIUS);
+    }
+
+    #[test]
+    fn color_is_paler_for_weak_charges_and_deeper_for_strong_ones() {
+        let lightness = |q: f32| {
+            let color = circle(q).color();
+            color.r + color.g + color.b
+        };
+        for sign in [1.0, -1.0] {
+            assert!(lightness(sign * 0.5 * Q) > lightness(sign * Q));
+            assert!(lightness(sign * 2.0 * Q) < lightness(sign * Q));
+            // Saturates beyond a quarter and four times the default charge
+            assert_eq!(lightness(sign * 0.1 * Q), lightness(sign * 0.25 * Q));
+            assert_eq!(lightness(sign * 10.0 * Q), lightness(sign * 4.0 * Q));
+        }
+        assert_eq!(circle(Q).color(), RED);
+        assert_eq!(circle(-Q).color(), BLUE);
+        assert_eq!(circle(0.0).color(), LIGHTGRAY);
+    }
 }
@@ -0,0 +1,516 @@
+use crate::charges::PointCharge;
+use crate::Cycle;
+use macroquad::math::Vec2;
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+
+/// Two overlapping charges, by index.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Contact {
+    pub first: usize,
+    pub second: usize,
+    /// Unit vector from the second charge to the first. When they coincide, along
+    /// x towards the one with the lower id, so that they still come apart.
+    pub normal: Vec2,
+    /// How far the circles of the charges overlap, in world pixels.
+    pub overlap: f32,
+}
+
+/// Uniform grid bucketing points by the cell holding them.
+pub(crate) struct SpatialHash {
+    cell_size: f32,
+    cells: HashMap<(i32, i32), Vec<usize>>,
+}
+
+impl SpatialHash {
+    fn new(points: impl IntoIterator<Item = Vec2>, cell_size: f32) -> Self {
+        let mut hash = SpatialHash { cell_size: cell_size.max(1.0), cells: HashMap::new() };
+        for (i, point) in points.into_iter().enumerate() {
+            hash.cells.entry(hash.cell(point)).or_default().push(i);
+        }
+        hash
+    }
+
+    #[allow(clippy::cast_possible_truncation)]
+    fn cell(&self, point: Vec2) -> (i32, i32) {
+        let cell = (point / self.cell_size).floor();
+        (cell.x as i32, cell.y as i32)
+    }
+
+    /// Buckets each circle in every cell its bounding square touches, so that
+    /// circles larger than the cells are found from any of the cells they cover.
+    /// Cells are as wide as a typical circle, so that one large circle does not
+    /// make every cell hold many small ones; it covers several cells instead.
+    pub(crate) fn with_circles(circles: &[(Vec2, f32)]) -> Self {
+        let mut radii: Vec<f32> = circles.iter().map(|(_center, radius)| *radius).collect();
+        let typical_radius = if radii.is_empty() {
+            0.0
+        } else {
+            let middle = radii.len() / 2;
+            *radii.select_nth_unstable_by(middle, f32::total_cmp).1
+        };
+        let mut hash = SpatialHash { cell_size: (2.0 * typical_radius).max(1.0), cells: HashMap::new() };
+        for (i, (center, radius)) in circles.iter().enumerate() {
+            for cell in hash.covered_cells(*center, *radius) {
+                hash.cells.entry(cell).or_default().push(i);
+            }
+        }
+        hash
+    }
+
+    /// Indices of the circles that may contain `point`, when bucketed with `with_circles`.
+    pub(crate) fn circles_near(&self, point: Vec2) -> impl Iterator<Item = usize> + '_ {
+        self.cells.get(&self.cell(point)).into_iter().flatten().copied()
+    }
+
+    /// Cells touched by the bounding square of a circle.
+    fn covered_cells(&self, center: Vec2, radius: f32) -> impl Iterator<Item = (i32, i32)> + use<> {
+        let (min_x, min_y) = self.cell(center - radius);
+        let (max_x, max_y) = self.cell(center + radius);
+        (min_x..=max_x).flat_map(move |x| (min_y..=max_y).map(move |y| (x, y)))
+    }
+
+    /// Indices of the points in the cells exactly `ring` cells away from the
+    /// cell of `point`, along x or y.
+    fn ring(&self, point: Vec2, ring: i32) -> impl Iterator<Item = usize> + '_ {
+        let (x, y) = self.cell(point);
+        let side = -ring..=ring;
+        // Top and bottom rows, then the columns between them
+        let rows = side.clone().flat_map(move |dx| [(dx, -ring), (dx, ring)]);
+        let columns = (-ring + 1..ring).flat_map(move |dy| [(-ring, dy), (ring, dy)]);
+        let offsets: Vec<(i32, i32)> = if ring == 0 { vec![(0, 0)] } else { rows.chain(columns).collect() };
+        offsets.into_iter()
+            .filter_map(move |(dx, dy)| self.cells.get(&(x.saturating_add(dx), y.saturating_add(dy))))
+            .flatten()
+            .copied()
+    }
+}
+
+/// Finds every pair of overlapping charges, bucketing them in a spatial hash
+/// so that only nearby charges are compared. Contacts come sorted by index,
+/// with `first` lower than `second`.
+#[must_use]
+pub fn find_contacts(charges: &[PointCharge]) -> Vec<Contact> {
+    let circles: Vec<(Vec2, f32)> = charges.iter()
+        .map(|charge| (charge.center, charge.drawing_circle.radius))
+        .collect();
+    let hash = SpatialHash::with_circles(&circles);
+    let mut contacts: Vec<Contact> = circles.iter().enumerate()
+        .flat_map(|(i, (center, radius))| {
+            // Overlapping circles have overlapping bounding squares, which share a cell
+            let mut candidates: Vec<usize> = hash.covered_cells(*center, *radius)
+                .filter_map(|cell| hash.cells.get(&cell))
+                .flatten()
+                .copied()
+                .filter(|&j| j > i)
+                .collect();
+            candidates.sort_unstable();
+            candidates.dedup();
+            let circles = &circles;
+            candidates.into_iter()
+                .filter_map(move |j| {
+                    let (other_center, other_radius) = circles[j];
+                    let min_distance = radius + other_radius;
+                    let distance_squared = center.distance_squared(other_center);
+                    let fallback = if charges[i].id < charges[j].id { Vec2::X } else { -Vec2::X };
+                    (distance_squared < min_distance.powi(2)).then(|| Contact {
+                        first: i,
+                        second: j,
+                        normal: (*center - other_center).try_normalize().unwrap_or(fallback),
+                        overlap: min_distance - distance_squared.sqrt(),
+                    })
+                })
+        })
+        .collect();
+    contacts.sort_unstable_by_key(|contact| (contact.first, contact.second));
+    contacts
+}
+
+/// Smallest distance between the centers of two charges that are not both
+/// fixed, infinite if there is no such pair. Searches a spatial hash outwards
+/// from each free charge, so it costs about O(N) for evenly spread charges.
+#[must_use]
+pub fn closest_distance(charges: &[PointCharge]) -> f32 {
+    let (min, max) = charges.iter()
+        .fold((Vec2::INFINITY, Vec2::NEG_INFINITY), |(min, max), charge| (min.min(charge.center), max.max(charge.center)));
+    if charges.len() < 2 || !(max - min).is_finite() {
+        return f32::INFINITY;
+    }
+    let extent = max - min;
+    // About one charge per cell
+    let cell_size = (extent.x.max(1.0) * extent.y.max(1.0) / charges.len() as f32).sqrt();
+    let hash = SpatialHash::new(charges.iter().map(|charge| charge.center), cell_size);
+    #[allow(clippy::cast_possible_truncation)]
+    let max_ring = (extent.max_element() / hash.cell_size).ceil() as i32 + 1;
+
+    charges.par_iter().enumerate()
+        .filter(|(_, charge)| !charge.is_fixed)
+        .map(|(i, charge)| {
+            let mut closest = f32::INFINITY;
+            for ring in 0..=max_ring {
+                // Points in this ring and beyond are at least this far
+                if closest <= (ring - 1) as f32 * hash.cell_size {
+                    break;
+                }
+                for j in hash.ring(charge.center, ring).filter(|&j| j != i) {
+                    closest = closest.min(charge.center.distance(charges[j].center));
+                }
+            }
+            closest
+        })
+        .reduce(|| f32::INFINITY, f32::min)
+}
+
+/// Fixed charges behave as if infinitely heavy, so that free ones bounce off them.
+fn inverse_mass(charge: &PointCharge) -> f32 {
+    if charge.is_fixed { 0.0 } else { 1.0 / charge.m }
+}
+
+/// Pushes the charges of each contact apart and bounces them with the
+/// restitution paired with the contact, fixed charges staying put. All contacts
+/// are measured before any charge moves, so the outcome does not depend on
+/// their order; a charge in several contacts moves by the mean of their
+/// corrections and takes the sum of their impulses.
+pub fn separate_and_bounce(charges: &mut [PointCharge], contacts: &[(Contact, f32)]) {
+    let mut corrections = vec![(Vec2::ZERO, 0u16); charges.len()];
+    let mut impulses = vec![Vec2::ZERO; charges.len()];
+    for (contact, restitution) in contacts {
+        let (first, second) = (&charges[contact.first], &charges[contact.second]);
+        if first.is_fixed && second.is_fixed {
+            continue;
+        }
+        // Only free charges move, sharing the overlap equally
+        let free = [!first.is_fixed, !second.is_fixed].map(f32::from);
+        let shares = free.map(|is_free| is_free / (free[0] + free[1]));
+        for (index, share, sign) in [(contact.first, shares[0], 1.0), (contact.second, shares[1], -1.0)] {
+            let (correction, count) = &mut corrections[index];
+            *correction += sign * contact.normal * contact.overlap * share;
+            *count += 1;
+        }
+
+        let velocity_along_normal = (first.velocity - second.velocity).dot(contact.normal);
+        // Only bounce charges moving toward each other
+        if velocity_along_normal >= 0.0 {
+            continue;
+        }
+        let impulse = -(1.0 + restitution) * velocity_along_normal / (inverse_mass(first) + inverse_mass(second)) * contact.normal;
+        impulses[contact.first] += impulse;
+        impulses[contact.second] -= impulse;
+    }
+
+    for ((charge, (correction, count)), impulse) in charges.iter_mut().zip(corrections).zip(impulses) {
+        if count > 0 {
+            charge.set_center(charge.center + correction / f32::from(count));
+        }
+        charge.velocity += impulse * inverse_mass(charge);
+    }
+}
+
+/// What becomes of two touching charges.
+#[derive(Clone, Debug)]
+pub enum Outcome {
+    /// Pushed apart, keeping this fraction of their approach speed.
+    Bounce { restitution: f32 },
+    /// Left to overlap.
+    PassThrough,
+    /// Both removed and replaced by these charges, if any.
+    Replace(Vec<PointCharge>),
+}
+
+/// Decides what happens when two charges touch.
+pub trait CollisionRule {
+    fn name(&self) -> &'static str;
+
+    /// Outcome of `first` touching `second`; `restitution` is the policy's
+    /// setting for inelastic bounces.
+    fn outcome(&self, first: &PointCharge, second: &PointCharge, restitution: f32) -> Outcome;
+}
+
+/// Bounces without losing kinetic energy.
+pub struct Elastic;
+
+/// Bounces keeping the policy's fraction of the approach speed.
+pub struct Inelastic;
+
+/// Fuses both charges into one at their center of mass, conserving charge,
+/// mass and momentum, so that +2 and -1 give +1.
+pub struct Merge;
+
+/// Lets charges overlap, only subject to their electric forces.
+pub struct PassThrough;
+
+/// Removes both charges.
+pub struct Annihilate;
+
+impl CollisionRule for Elastic {
+    fn name(&self) -> &'static str {
+        "elastic"
+    }
+
+    fn outcome(&self, _first: &PointCharge, _second: &PointCharge, _restitution: f32) -> Outcome {
+        Outcome::Bounce { restitution: 1.0 }
+    }
+}
+
+impl CollisionRule for Inelastic {
+    fn name(&self) -> &'static str {
+        "inelastic"
+    }
+
+    fn outcome(&self, _first: &PointCharge, _second: &PointCharge, restitution: f32) -> Outcome {
+        Outcome::Bounce { restitution }
+    }
+}
+
+impl CollisionRule for Merge {
+    fn name(&self) -> &'static str {
+        "merge"
+    }
+
+    fn outcome(&self, first: &PointCharge, second: &PointCharge, _restitution: f32) -> Outcome {
+        let m = first.m + second.m;
+        let (center, velocity) = if m > 0.0 {
+            ((first.center * first.m + second.center * second.m) / m, (first.velocity * first.m + second.velocity * second.m) / m)
+        } else {
+            ((first.center + second.center) / 2.0, Vec2::ZERO)
+        };
+        // Reuse an id
+        let mut merged = PointCharge::new(first.id.min(second.id), center, first.q() + second.q(), first.is_fixed && second.is_fixed);
+        merged.m = m;
+        // Charges of the same species stay that species' color
+        if first.color() == second.color() {
+            merged.set_color(first.color());
+        }
+        merged.velocity = if merged.is_fixed { Vec2::ZERO } else { velocity };
+        Outcome::Replace(vec![merged])
+    }
+}
+
+impl CollisionRule for PassThrough {
+    fn name(&self) -> &'static str {
+        "pass-through"
+    }
+
+    fn outcome(&self, _first: &PointCharge, _second: &PointCharge, _restitution: f32) -> Outcome {
+        Outcome::PassThrough
+    }
+}
+
+impl CollisionRule for Annihilate {
+    fn name(&self) -> &'static str {
+        "annihilate"
+    }
+
+    fn outcome(&self, _first: &PointCharge, _second: &PointCharge, _restitution: f32) -> Outcome {
+        Outcome::Replace(vec![])
+    }
+}
+
+/// Collision rule applied to one kind of pair by a `CollisionPolicy`, saved
+/// with scenes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum CollisionKind {
+    Elastic,
+    Inelastic,
+    Merge,
+    PassThrough,
+    Annihilate,
+}
+
+impl CollisionKind {
+    #[must_use]
+    pub fn rule(self) -> &'static dyn CollisionRule {
+        match self {
+            CollisionKind::Elastic => &Elastic,
+            CollisionKind::Inelastic => &Inelastic,
+            CollisionKind::Merge => &Merge,
+            CollisionKind::PassThrough => &PassThrough,
+            CollisionKind::Annihilate => &Annihilate,
+        }
+    }
+}
+
+impl Cycle for CollisionKind {
+    const ALL: &'static [Self] = &[
+        CollisionKind::Elastic,
+        CollisionKind::Inelastic,
+        CollisionKind::Merge,
+        CollisionKind::PassThrough,
+        CollisionKind::Annihilate,
+    ];
+}
+
+impl fmt::Display for CollisionKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.rule().name())
+    }
+}
+
+/// Which rule applies to each kind of pair: charges of opposite signs, and
+/// charges of the same sign or involving a neutral one.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct CollisionPolicy {
+    pub like_signs: CollisionKind,
+    pub opposite_signs: CollisionKind,
+    /// Fraction of the approach speed kept by inelastic bounces, from 0 to 1.
+    pub restitution: f32,
+}
+
+impl Default for CollisionPolicy {
+    fn default() -> Self {
+        CollisionPolicy {
+            like_signs: CollisionKind::Inelastic,
+            opposite_signs: CollisionKind::Merge,
+            restitution: Self::DEFAULT_RESTITUTION,
+        }
+    }
+}
+
+impl CollisionPolicy {
+    pub const DEFAULT_RESTITUTION: f32 = 0.5;
+    const RESTITUTION_STEP: f32 = 0.1;
+
+    #[must_use]
+    pub fn outcome(&self, first: &PointCharge, second: &PointCharge) -> Outcome {
+        let kind = if first.has_opposite_sign_to(second) { self.opposite_signs } else { self.like_signs };
+        kind.rule().outcome(first, second, self.restitution)
+    }
+
+    pub fn more_restitution(&mut self) {
+        self.restitution = (self.restitution + Self::RESTITUTION_STEP).min(1.0);
+    }
+
+    pub fn less_restitution(&mut self) {
+        self.restitution = (self.restitution - Self::RESTITUTION_STEP).max(0.0);
+    }
+}
+
+impl fmt::Display for CollisionPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "like signs {}, opposite signs {}, restitution {:.1}", self.like_signs, self.opposite_signs, self.restitution)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const Q: f32 = PointCharge::DEFAULT_CHARGE;
+
+    fn charge(id: usize, x: f32, q: f32, m: f32, velocity: Vec2) -> PointCharge {
+        let mut charge = PointCharge::new(id, Vec2::new(x, 0.0), q, false);
+        charge.m = m;
+        charge.velocity = velocity;
+        charge
+    }
+
+    fn policy(like_signs: CollisionKind, opposite_signs: CollisionKind) -> CollisionPolicy {
+        CollisionPolicy { like_signs, opposite_signs, ..CollisionPolicy::default() }
+    }
+
+    #[test]
+    fn merging_conserves_charge_mass_and_momentum() {
+        let first = charge(3, 0.0, 2.0 * Q, 2.0, Vec2::new(1.0, 0.0));
+        let second = charge(7, 10.0, -Q, 3.0, Vec2::new(-2.0, 1.0));
+        let Outcome::Replace(merged) = Merge.outcome(&first, &second, 0.0) else {
+            panic!("charges did not merge");
+        };
+        let [merged] = merged.as_slice() else {
+            panic!("{} charges instead of one", merged.len());
+        };
+        assert_eq!(merged.id, 3);
+        assert!((merged.q() - Q).abs() <= 1e-6 * Q);
+        assert_eq!(merged.sign, first.sign);
+        assert!((merged.m - 5.0).abs() <= 1e-6);
+        assert!((merged.m * merged.velocity - Vec2::new(-4.0, 3.0)).length() <= 1e-5);
+        assert!((merged.center - Vec2::new(6.0, 0.0)).length() <= 1e-5);
+    }
+
+    #[test]
+    fn annihilation_removes_both_charges() {
+        let policy = policy(CollisionKind::Elastic, CollisionKind::Annihilate);
+        let first = charge(0, 0.0, Q, 1.0, Vec2::ZERO);
+        let second = charge(1, 10.0, -Q, 1.0, Vec2::ZERO);
+        assert!(matches!(policy.outcome(&first, &second), Outcome::Replace(charges) if charges.is_empty()));
+    }
+
+    #[test]
+    fn policy_splits_pairs_by_sign() {
+        let policy = policy(CollisionKind::Elastic, CollisionKind::Merge);
+        let positive = charge(0, 0.0, Q, 1.0, Vec2::ZERO);
+        let other_positive = charge(1, 10.0, 2.0 * Q, 1.0, Vec2::ZERO);
+        let negative = charge(2, 20.0, -Q, 1.0, Vec2::ZERO);
+        let neutral = charge(3, 30.0, 0.0, 1.0, Vec2::ZERO);
+        assert!(matches!(policy.outcome(&positive, &other_positive), Outcome::Bounce { restitution } if restitution == 1.0));
+        assert!(matches!(policy.outcome(&positive, &neutral), Outcome::Bounce { .. }));
+        assert!(matches!(policy.outcome(&negative, &neutral), Outcome::Bounce { .. }));
+        assert!(matches!(policy.outcome(&positive, &negative), Outcome::Replace(_)));
+        assert!(matches!(policy.outcome(&negative, &other_positive), Outcome::Replace(_)));
+    }
+
+    #[test]
+    fn free_charges_bounce_off_fixed_ones() {
+        let mut charges = vec![
+            charge(0, 0.0, Q, 1.0, Vec2::new(10.0, 0.0)),
+            PointCharge::new(1, Vec2::new(PointCharge::DEFAULT_RADIUS, 0.0), Q, true),
+        ];
+        let contacts: Vec<(Contact, f32)> = find_contacts(&charges).into_iter().map(|contact| (contact, 1.0)).collect();
+        assert_eq!(contacts.len(), 1);
+        separate_and_bounce(&mut charges, &contacts);
+        assert!((charges[0].velocity - Vec2::new(-10.0, 0.0)).length() <= 1e-4, "{}", charges[0].velocity);
+        assert_eq!(charges[1].center, Vec2::new(PointCharge::DEFAULT_RADIUS, 0.0));
+        assert_eq!(charges[1].velocity, Vec2::ZERO);
+        assert!(find_contacts(&charges).is_empty());
+    }
+
+    #[test]
+    fn contacts_are_found_between_nearby_charges_only() {
+        let charges: Vec<PointCharge> = [0.0, 20.0, 35.0, 200.0]
+            .into_iter()
+            .enumerate()
+            .map(|(id, x)| charge(id, x, Q, 1.0, Vec2::ZERO))
+            .collect();
+        let pairs: Vec<(usize, usize)> = find_contacts(&charges).iter().map(|contact| (contact.first, contact.second)).collect();
+        assert_eq!(pairs, [(0, 1), (1, 2)]);
+    }
+
+    #[test]
+    fn contacts_with_large_charges_are_found_across_cells() {
+        // Mostly small charges and a few large ones, against comparing every pair
+        let charges: Vec<PointCharge> = (0..200)
+            .map(|id| {
+                let q = if id % 25 == 0 { 8.0 * Q } else { 0.5 * Q };
+                let mut charge = PointCharge::new(id, Vec2::new((id * 37 % 101) as f32 * 4.0, (id * 53 % 97) as f32 * 4.0), q, false);
+                charge.m = 1.0;
+                charge
+            })
+            .collect();
+        let mut expected = vec![];
+        for i in 0..charges.len() {
+            for j in i + 1..charges.len() {
+                let min_distance = charges[i].drawing_circle.radius + charges[j].drawing_circle.radius;
+                if charges[i].center.distance_squared(charges[j].center) < min_distance.powi(2) {
+                    expected.push((i, j));
+                }
+            }
+        }
+        let pairs: Vec<(usize, usize)> = find_contacts(&charges).iter().map(|contact| (contact.first, contact.second)).collect();
+        assert!(expected.iter().any(|(i, _)| i % 25 == 0));
+        assert_eq!(pairs, expected);
+    }
+
+    #[test]
+    fn coincident_charges_come_apart_the_same_way_in_any_order() {
+        let at_origin = |id| charge(id, 0.0, Q, 1.0, Vec2::ZERO);
+        for mut charges in [vec![at_origin(4), at_origin(9)], vec![at_origin(9), at_origin(4)]] {
+            let contacts: Vec<(Contact, f32)> = find_contacts(&charges).into_iter().map(|contact| (contact, 1.0)).collect();
+            assert_eq!(contacts.len(), 1);
+            separate_and_bounce(&mut charges, &contacts);
+            charges.sort_by_key(|charge| charge.id);
+            assert_eq!(charges[0].center, Vec2::new(PointCharge::DEFAULT_RADIUS, 0.0));
+            assert_eq!(charges[1].center, Vec2::new(-PointCharge::DEFAULT_RADIUS, 0.0));
+        }
+    }
+}
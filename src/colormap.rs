@@ -0,0 +1,239 @@
+use crate::Cycle;
+use macroquad::color::Color;
+use std::fmt;
+
+/// Color scales for the heatmaps. All but `Inferno` are diverging, from the
+/// most negative value through zero to the most positive one.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Colormap {
+    /// Blue for negative and red for positive potentials, fading to black at zero.
+    #[default]
+    Classic,
+    /// Moreland's perceptually uniform blue-white-red map.
+    CoolWarm,
+    /// ColorBrewer's purple-orange map, readable with the common forms of color blindness.
+    PurpleOrange,
+    /// Sequential black-red-yellow map, for magnitudes rather than signed values;
+    /// left out of `ALL` for that reason.
+    Inferno,
+}
+
+impl Colormap {
+    #[must_use]
+    pub fn name(self) -> &'static str {
+        match self {
+            Colormap::Classic => "Classic",
+            Colormap::CoolWarm => "Cool-warm",
+            Colormap::PurpleOrange => "Purple-orange (colorblind-safe)",
+            Colormap::Inferno => "Inferno",
+        }
+    }
+
+    /// Whether the map runs from zero to a maximum instead of around zero.
+    #[must_use]
+    pub fn is_sequential(self) -> bool {
+        self == Colormap::Inferno
+    }
+
+    /// Control points, evenly spread from -1 to 1.
+    fn control_points(self) -> &'static [[u8; 3]] {
+        match self {
+            Colormap::Classic => &[[0, 0, 255], [0, 0, 0], [255, 0, 0]],
+            Colormap::CoolWarm => &[
+                [59, 76, 192], [98, 130, 234], [141, 176, 254], [184, 208, 249], [221, 221, 221],
+                [245, 196, 173], [244, 154, 123], [222, 96, 77], [180, 4, 38],
+            ],
+            Colormap::PurpleOrange => &[
+                [45, 0, 75], [84, 39, 136], [128, 115, 172], [178, 171, 210], [216, 218, 235], [247, 247, 247],
+                [254, 224, 182], [253, 184, 99], [224, 130, 20], [179, 88, 6], [127, 59, 8],
+            ],
+            Colormap::Inferno => &[
+                [0, 0, 4], [40, 11, 84], [101, 21, 110], [159, 42, 99], [212, 72, 66],
+                [245, 125, 21], [250, 193, 39], [252, 255, 164],
+            ],
+        }
+    }
+
+    /// Color of `t`, from -1 to 1, interpolating linearly between control points.
+    #[must_use]
+    pub fn color(self, t: f32) -> Color {
+        let points = self.control_points();
+        let position = (t.clamp(-1.0, 1.0) + 1.0) / 2.0 * (points.len() - 1) as f32;
+        #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+        let index = (position as usize).min(points.len() - 2);
+        let fraction = position - index as f32;
+        let [from, to] = [points[index], points[index + 1]];
+        let channel = |i: usize| (f32::from(from[i]) + (f32::from(to[i]) - f32::from(from[i])) * fraction) / 255.0;
+        Color::new(channel(0), channel(1), channel(2), 1.0)
+    }
+}
+
+impl Cycle for Colormap {
+    const ALL: &'static [Self] = &[Colormap::Classic, Colormap::CoolWarm, Colormap::PurpleOrange];
+}
+
+impl fmt::Display for Colormap {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+/// How values are compressed into the colormap, relative to the full-scale range.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Scaling {
+    Linear,
+    /// Logarithmic in the magnitude over three decades below the range; anything
+    /// smaller maps to zero.
+    Log,
+    /// Logarithmic in the magnitude, turning linear near zero.
+    Symlog,
+    /// Linear near zero and logarithmic far from it, but smoother than `Symlog`.
+    #[default]
+    Arcsinh,
+}
+
+impl Scaling {
+    // Fractions of the range below which the scalings stop being logarithmic
+    const LOG_FLOOR: f32 = 1e-3;
+    const SYMLOG_THRESHOLD: f32 = 1e-3;
+    const ARCSINH_WIDTH: f32 = 1e-2;
+
+    #[must_use]
+    pub fn name(self) -> &'static str {
+        match self {
+            Scaling::Linear => "linear",
+            Scaling::Log => "log",
+            Scaling::Symlog => "symlog",
+            Scaling::Arcsinh => "arcsinh",
+        }
+    }
+
+    /// Maps `value` to -1..1, with `range` and `-range` at the ends.
+    #[must_use]
+    pub fn normalize(self, value: f32, range: f32) -> f32 {
+        let range = range.max(f32::MIN_POSITIVE);
+        let magnitude = value.abs();
+        let t = match self {
+            Scaling::Linear => magnitude / range,
+            Scaling::Log => {
+                let floor = range * Self::LOG_FLOOR;
+                if magnitude <= floor { 0.0 } else { (magnitude / floor).ln() / (range / floor).ln() }
+            }
+            Scaling::Symlog => {
+                let threshold = range * Self::SYMLOG_THRESHOLD;
+                (magnitude / threshold).ln_1p() / (range / threshold).ln_1p()
+            }
+            Scaling::Arcsinh => {
+                let width = range * Self::ARCSINH_WIDTH;
+                (magnitude / width).asinh() / (range / width).asinh()
+            }
+        };
+        value.signum() * t.min(1.0)
+    }
+
+    /// Inverse of `normalize`, for `t` within -1..1.
+    #[must_use]
+    pub fn denormalize(self, t: f32, range: f32) -> f32 {
+        let range = range.max(f32::MIN_POSITIVE);
+        let magnitude = match self {
+            Scaling::Linear => t.abs() * range,
+            Scaling::Log => {
+                let floor = range * Self::LOG_FLOOR;
+                if t == 0.0 { 0.0 } else { floor * (t.abs() * (range / floor).ln()).exp() }
+            }
+            Scaling::Symlog => {
+                let threshold = range * Self::SYMLOG_THRESHOLD;
+                threshold * (t.abs() * (range / threshold).ln_1p()).exp_m1()
+            }
+            Scaling::Arcsinh => {
+                let width = range * Self::ARCSINH_WIDTH;
+                width * (t.abs() * (range / width).asinh()).sinh()
+            }
+        };
+        t.signum() * magnitude
+    }
+}
+
+impl Cycle for Scaling {
+    const ALL: &'static [Self] = &[Scaling::Linear, Scaling::Log, Scaling::Symlog, Scaling::Arcsinh];
+}
+
+impl fmt::Display for Scaling {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+/// Maps potentials or field values to colors: a colormap, a scaling and the
+/// full-scale range, either fixed or fitted to the data.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ColorScale {
+    pub colormap: Colormap,
+    pub scaling: Scaling,
+    /// Magnitude at the ends of the colormap, in the unit of the mapped values.
+    pub range: f32,
+    /// Whether `update_range` fits the range to the data instead of using a full-scale value.
+    pub auto_range: bool,
+    /// Fraction of the samples whose magnitude stays within an automatic range.
+    pub percentile: f32,
+}
+
+impl Default for ColorScale {
+    fn default() -> Self {
+        ColorScale {
+            colormap: Colormap::default(),
+            scaling: Scaling::default(),
+            range: 1.0,
+            auto_range: true,
+            percentile: Self::DEFAULT_PERCENTILE,
+        }
+    }
+}
+
+impl ColorScale {
+    pub const DEFAULT_PERCENTILE: f32 = 0.95;
+
+    #[must_use]
+    pub fn color(&self, value: f32) -> Color {
+        let t = self.scaling.normalize(value, self.range);
+        if self.colormap.is_sequential() {
+            // Only magnitudes are expected, spread over the whole map
+            self.colormap.color(2.0 * t.abs() - 1.0)
+        } else {
+            self.colormap.color(t)
+        }
+    }
+
+    /// Value shown at `fraction` of the way up a colorbar, from 0 to 1.
+    #[must_use]
+    pub fn value_at(&self, fraction: f32) -> f32 {
+        let t = if self.colormap.is_sequential() { fraction } else { 2.0 * fraction - 1.0 };
+        self.scaling.denormalize(t, self.range)
+    }
+
+    /// Sets the range to the percentile of the magnitudes of `values`, or to
+    /// `full_scale` when auto-ranging is off or the values are all zero.
+    pub fn update_range(&mut self, values: impl IntoIterator<Item = f32>, full_scale: f32) {
+        self.range = if self.auto_range {
+            percentile_magnitude(values, self.percentile).unwrap_or(full_scale)
+        } else {
+            full_scale
+        };
+    }
+}
+
+/// The magnitude below which `percentile` of the finite values lie, if it is not zero.
+#[must_use]
+pub fn percentile_magnitude(values: impl IntoIterator<Item = f32>, percentile: f32) -> Option<f32> {
+    let mut magnitudes: Vec<f32> = values.into_iter()
+        .map(f32::abs)
+        .filter(|magnitude| magnitude.is_finite())
+        .collect();
+    if magnitudes.is_empty() {
+        return None;
+    }
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    let index = ((magnitudes.len() - 1) as f32 * percentile.clamp(0.0, 1.0)).round() as usize;
+    let (_, magnitude, _) = magnitudes.select_nth_unstable_by(index, f32::total_cmp);
+    (*magnitude > 0.0).then_some(*magnitude)
+}
@@ -0,0 +1,396 @@
+use crate::Cycle;
+use macroquad::math::Vec2;
+use ndarray::Array2;
+use rusty_fitpack::{splev, splrep};
+use std::collections::HashMap;
+use std::fmt;
+
+/// An equipotential line as a polyline in world pixels.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Contour {
+    /// Potential along the line, in volts.
+    pub level: f32,
+    pub points: Vec<Vec2>,
+    /// Whether the last point joins back to the first one; open contours end on
+    /// the border of the sampled region.
+    pub is_closed: bool,
+}
+
+impl Contour {
+    const SPLINE_DEGREE: usize = 3;
+
+    /// Fits a least-squares cubic spline through the points with knots every
+    /// `knot_spacing` world pixels along the line, which smooths out wiggles
+    /// shorter than that, and resamples it with as many points. Closed contours
+    /// are wrapped around before fitting, so that the spline runs smoothly across
+    /// the point where they close. Contours too short to fit are returned unchanged.
+    #[must_use]
+    pub fn smoothed(&self, knot_spacing: f32) -> Contour {
+        // Chord-length parametrization, which must strictly increase
+        let mut parameters: Vec<f64> = vec![];
+        let mut xs: Vec<f64> = vec![];
+        let mut ys: Vec<f64> = vec![];
+        let mut previous: Option<Vec2> = None;
+        for point in &self.points {
+            let length = previous.map_or(0.0, |previous| previous.distance(*point));
+            if previous.is_some() && length < 1e-3 {
+                continue;
+            }
+            parameters.push(parameters.last().copied().unwrap_or(0.0) + f64::from(length));
+            xs.push(f64::from(point.x));
+            ys.push(f64::from(point.y));
+            previous = Some(*point);
+        }
+        let count = parameters.len();
+        let total_length = parameters.last().copied().unwrap_or(0.0);
+        // Fewer knots than points, so every knot interval holds data
+        #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+        let intervals = ((total_length / f64::from(knot_spacing.max(1.0))) as usize).min(count / 4);
+        if intervals < 2 {
+            return self.clone();
+        }
+        if self.is_closed {
+            // Half a turn on either side; the last point repeats the first one
+            let wrap = count / 2;
+            let before = (count - 1 - wrap..count - 1).map(|i| (parameters[i] - total_length, xs[i], ys[i]));
+            let after = (1..=wrap).map(|i| (parameters[i] + total_length, xs[i], ys[i]));
+            let wrapped: Vec<(f64, f64, f64)> = before
+                .chain((0..count).map(|i| (parameters[i], xs[i], ys[i])))
+                .chain(after)
+                .collect();
+            parameters = wrapped.iter().map(|(parameter, _, _)| *parameter).collect();
+            xs = wrapped.iter().map(|(_, x, _)| *x).collect();
+            ys = wrapped.iter().map(|(_, _, y)| *y).collect();
+        }
+        let (first, last) = (parameters[0], parameters[parameters.len() - 1]);
+        let knots: Vec<f64> = (-(intervals as isize)..2 * intervals as isize)
+            .map(|i| total_length * i as f64 / intervals as f64)
+            .filter(|knot| *knot > first && *knot < last)
+            .collect();
+
+        let fit = |values: Vec<f64>| splrep(parameters.clone(), values, None, None, None, Some(Self::SPLINE_DEGREE), None, None, Some(knots.clone()), None, None, None);
+        let (x_knots, x_coefficients, _) = fit(xs);
+        let (y_knots, y_coefficients, _) = fit(ys);
+
+        let samples: Vec<f64> = (0..count).map(|i| total_length * i as f64 / (count - 1) as f64).collect();
+        let xs = splev(x_knots, x_coefficients, Self::SPLINE_DEGREE, samples.clone(), 3);
+        let ys = splev(y_knots, y_coefficients, Self::SPLINE_DEGREE, samples, 3);
+        #[allow(clippy::cast_possible_truncation)]
+        let mut points: Vec<Vec2> = xs.into_iter().zip(ys).map(|(x, y)| Vec2::new(x as f32, y as f32)).collect();
+        if self.is_closed {
+            points[count - 1] = points[0];
+        }
+        Contour { level: self.level, points, is_closed: self.is_closed }
+    }
+}
+
+/// A side of a grid cell: the samples it joins are `[x, y]` and the next one
+/// along x (horizontal) or along y.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+struct Edge {
+    x: usize,
+    y: usize,
+    is_horizontal: bool,
+}
+
+/// Extracts the lines where the sampled potential equals `level` with marching
+/// squares, interpolating linearly along the sides of each cell. Cells touching
+/// a non-finite sample, as at the center of a charge, are skipped.
+#[must_use]
+pub fn extract_contours(samples: &Array2<(Vec2, f32)>, level: f32) -> Vec<Contour> {
+    let (width, height) = samples.dim();
+    let mut crossings: HashMap<Edge, Vec2> = HashMap::new();
+    let mut segments: Vec<[Edge; 2]> = vec![];
+
+    let mut crossing = |edge: Edge| -> Edge {
+        crossings.entry(edge).or_insert_with(|| {
+            let end = if edge.is_horizontal { [edge.x + 1, edge.y] } else { [edge.x, edge.y + 1] };
+            let (start_point, start_value) = samples[[edge.x, edge.y]];
+            let (end_point, end_value) = samples[end];
+            let t = (level - start_value) / (end_value - start_value);
+            start_point + t * (end_point - start_point)
+        });
+        edge
+    };
+
+    for x in 0..width.saturating_sub(1) {
+        for y in 0..height.saturating_sub(1) {
+            // Corners in order around the cell
+            let corners = [[x, y], [x + 1, y], [x + 1, y + 1], [x, y + 1]].map(|corner| samples[corner].1);
+            if corners.iter().any(|value| !value.is_finite()) {
+                continue;
+            }
+            let is_above = corners.map(|value| value >= level);
+            // Side i joins corner i to corner i + 1
+            let sides = [
+                Edge { x, y, is_horizontal: true },
+                Edge { x: x + 1, y, is_horizontal: false },
+                Edge { x, y: y + 1, is_horizontal: true },
+                Edge { x, y, is_horizontal: false },
+            ];
+            let crossed: Vec<usize> = (0..4).filter(|&i| is_above[i] != is_above[(i + 1) % 4]).collect();
+            match crossed.len() {
+                2 => segments.push([crossing(sides[crossed[0]]), crossing(sides[crossed[1]])]),
+                4 => {
+                    // Saddle: the center decides which opposite corners are connected
+                    let center_is_above = corners.iter().sum::<f32>() / 4.0 >= level;
+                    let pairs = if center_is_above == is_above[0] { [(0, 1), (2, 3)] } else { [(3, 0), (1, 2)] };
+                    for (first, second) in pairs {
+                        segments.push([crossing(sides[first]), crossing(sides[second])]);
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    join_segments(&segments, &crossings, level)
+}
+
+/// Chains segments sharing an edge into polylines, starting with the open ones.
+fn join_segments(segments: &[[Edge; 2]], crossings: &HashMap<Edge, Vec2>, level: f32) -> Vec<Contour> {
+    let mut incident: HashMap<Edge, Vec<usize>> = HashMap::new();
+    for (i, segment) in segments.iter().enumerate() {
+        for edge in segment {
+            incident.entry(*edge).or_default().push(i);
+        }
+    }
+    let mut is_used = vec![false; segments.len()];
+
+    let walk = |first_segment: usize, first_edge: Edge, is_used: &mut Vec<bool>| -> Contour {
+        let mut points = vec![crossings[&first_edge]];
+        let (mut segment, mut edge) = (first_segment, first_edge);
+        loop {
+            is_used[segment] = true;
+            edge = if segments[segment][0] == edge { segments[segment][1] } else { segments[segment][0] };
+            points.push(crossings[&edge]);
+            match incident[&edge].iter().find(|&&next| !is_used[next]) {
+                Some(&next) => segment = next,
+                None => break,
+            }
+        }
+        Contour { level, points, is_closed: edge == first_edge }
+    };
+
+    let mut contours = vec![];
+    // Open contours start on an edge used by a single segment, on the border
+    for i in 0..segments.len() {
+        for edge in segments[i] {
+            if !is_used[i] && incident[&edge].len() == 1 {
+                contours.push(walk(i, edge, &mut is_used));
+            }
+        }
+    }
+    for i in 0..segments.len() {
+        if !is_used[i] {
+            contours.push(walk(i, segments[i][0], &mut is_used));
+        }
+    }
+    contours
+}
+
+/// How automatic contour levels are spread over the potential range.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum LevelSpacing {
+    #[default]
+    Linear,
+    /// Even in `sign(V) log(1 + |V| / V0)`, so that levels keep coming both close
+    /// to the charges and far from them, on either side of zero.
+    Logarithmic,
+}
+
+impl Cycle for LevelSpacing {
+    const ALL: &'static [Self] = &[LevelSpacing::Linear, LevelSpacing::Logarithmic];
+}
+
+impl fmt::Display for LevelSpacing {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LevelSpacing::Linear => write!(f, "linear"),
+            LevelSpacing::Logarithmic => write!(f, "logarithmic"),
+        }
+    }
+}
+
+/// Contour levels spread over the range of the sampled potential, following it as it changes.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct AutoLevels {
+    pub enabled: bool,
+    pub count: usize,
+    pub spacing: LevelSpacing,
+}
+
+impl Default for AutoLevels {
+    fn default() -> Self {
+        AutoLevels { enabled: false, count: Self::DEFAULT_COUNT, spacing: LevelSpacing::default() }
+    }
+}
+
+impl AutoLevels {
+    pub const DEFAULT_COUNT: usize = 10;
+    pub const MAX_COUNT: usize = 50;
+
+    pub fn more(&mut self) {
+        self.count = (self.count + 1).min(Self::MAX_COUNT);
+    }
+
+    pub fn fewer(&mut self) {
+        self.count = self.count.saturating_sub(1).max(1);
+    }
+
+    /// The levels for the current samples, none when they are all equal.
+    #[must_use]
+    pub fn levels(&self, samples: &Array2<(Vec2, f32)>) -> Vec<f32> {
+        potential_range(samples)
+            .map(|(min, max)| spaced_levels(min, max, self.count, self.spacing))
+            .unwrap_or_default()
+    }
+}
+
+/// Smallest and largest finite samples, if they differ.
+#[must_use]
+pub fn potential_range(samples: &Array2<(Vec2, f32)>) -> Option<(f32, f32)> {
+    let (min, max) = samples.iter()
+        .map(|(_point, potential)| *potential)
+        .filter(|potential| potential.is_finite())
+        .fold((f32::INFINITY, f32::NEG_INFINITY), |(min, max), potential| (min.min(potential), max.max(potential)));
+    (min < max).then_some((min, max))
+}
+
+/// `count` levels strictly between `min` and `max`, evenly spaced according to `spacing`.
+#[must_use]
+pub fn spaced_levels(min: f32, max: f32, count: usize, spacing: LevelSpacing) -> Vec<f32> {
+    // Potential below which the logarithmic spacing turns linear
+    let knee = min.abs().max(max.abs()) * 1e-4;
+    let forward = |v: f32| match spacing {
+        LevelSpacing::Linear => v,
+        LevelSpacing::Logarithmic => v.signum() * (v.abs() / knee).ln_1p(),
+    };
+    let inverse = |t: f32| match spacing {
+        LevelSpacing::Linear => t,
+        LevelSpacing::Logarithmic => t.signum() * knee * t.abs().exp_m1(),
+    };
+    let (low, high) = (forward(min), forward(max));
+    (1..=count)
+        .map(|i| inverse(low + (high - low) * i as f32 / (count + 1) as f32))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Samples of `potential` on a `width` by `height` grid one pixel apart.
+    fn sample(width: usize, height: usize, potential: impl Fn(Vec2) -> f32) -> Array2<(Vec2, f32)> {
+        Array2::from_shape_fn((width, height), |(x, y)| {
+            let point = Vec2::new(x as f32, y as f32);
+            (point, potential(point))
+        })
+    }
+
+    #[test]
+    fn contour_around_a_charge_is_one_closed_circle() {
+        let charge = Vec2::new(20.3, 20.6);
+        let samples = sample(41, 41, |point| 1.0 / point.distance(charge));
+        let contours = extract_contours(&samples, 0.1);
+
+        assert_eq!(contours.len(), 1);
+        let contour = &contours[0];
+        assert!(contour.is_closed);
+        assert_eq!(contour.points.first(), contour.points.last());
+        for point in &contour.points {
+            assert!((point.distance(charge) - 10.0).abs() < 0.5, "{point} is off the circle");
+        }
+    }
+
+    #[test]
+    fn saddle_cell_is_split_by_its_center() {
+        // The center, at the mean of the corners, is above the level: the two low corners are cut off
+        let samples = Array2::from_shape_fn((2, 2), |(x, y)| (Vec2::new(x as f32, y as f32), if x == y { 1.0 } else { 0.0 }));
+        let mut contours: Vec<Vec<Vec2>> = extract_contours(&samples, 0.5).into_iter()
+            .map(|contour| {
+                assert!(!contour.is_closed);
+                let mut points = contour.points;
+                points.sort_by(|a, b| a.x.total_cmp(&b.x));
+                points
+            })
+            .collect();
+        contours.sort_by(|a, b| a[0].x.total_cmp(&b[0].x));
+
+        assert_eq!(contours, vec![
+            vec![Vec2::new(0.0, 0.5), Vec2::new(0.5, 1.0)],
+            vec![Vec2::new(0.5, 0.0), Vec2::new(1.0, 0.5)],
+        ]);
+    }
+
+    #[test]
+    fn contour_crossing_the_grid_is_open() {
+        let samples = sample(5, 5, |point| point.x);
+        let contours = extract_contours(&samples, 2.5);
+
+        assert_eq!(contours.len(), 1);
+        let contour = &contours[0];
+        assert!(!contour.is_closed);
+        assert_eq!(contour.points.len(), 5);
+        assert!(contour.points.iter().all(|point| point.x == 2.5));
+        // Walked from one border to the other
+        let ends = [contour.points[0].y, contour.points[4].y];
+        assert!(ends == [0.0, 4.0] || ends == [4.0, 0.0], "{ends:?}");
+    }
+
+    #[test]
+    fn segments_are_joined_in_order_whatever_order_they_come_in() {
+        let edges: Vec<Edge> = (0..4).map(|y| Edge { x: 0, y, is_horizontal: true }).collect();
+        let crossings: HashMap<Edge, Vec2> = edges.iter().map(|edge| (*edge, Vec2::new(0.5, edge.y as f32))).collect();
+        let segments = [[edges[2], edges[3]], [edges[0], edges[1]], [edges[2], edges[1]]];
+        let contours = join_segments(&segments, &crossings, 1.0);
+
+        assert_eq!(contours.len(), 1);
+        let ys: Vec<f32> = contours[0].points.iter().map(|point| point.y).collect();
+        assert!(ys == [0.0, 1.0, 2.0, 3.0] || ys == [3.0, 2.0, 1.0, 0.0], "{ys:?}");
+        assert!(!contours[0].is_closed);
+    }
+
+    #[test]
+    fn linear_levels_are_evenly_spaced_inside_the_range() {
+        assert_eq!(spaced_levels(0.0, 10.0, 4, LevelSpacing::Linear), vec![2.0, 4.0, 6.0, 8.0]);
+    }
+
+    #[test]
+    fn logarithmic_levels_crowd_towards_zero_on_either_side() {
+        let levels = spaced_levels(-1000.0, 1000.0, 5, LevelSpacing::Logarithmic);
+
+        assert_eq!(levels.len(), 5);
+        assert!(levels.windows(2).all(|pair| pair[0] < pair[1]), "{levels:?}");
+        assert!(levels[2].abs() < 1e-3, "{levels:?}");
+        for (negative, positive) in levels.iter().zip(levels.iter().rev()) {
+            assert!((negative + positive).abs() <= 1e-3 * positive.abs().max(1.0), "{levels:?}");
+        }
+        // Linear spacing would put the first positive level at a third of the way up
+        assert!(levels[3] < 1000.0 / 30.0, "{levels:?}");
+    }
+
+    #[test]
+    fn smoothed_closed_contour_has_no_kink_where_it_closes() {
+        // A wiggly circle, starting where a wiggle is at its steepest
+        let points: Vec<Vec2> = (0..=200)
+            .map(|i| {
+                let angle = std::f32::consts::TAU * i as f32 / 200.0;
+                (100.0 + 2.0 * (25.0 * angle).sin()) * Vec2::from_angle(angle)
+            })
+            .collect();
+        let contour = Contour { level: 0.0, points, is_closed: true }.smoothed(30.0);
+
+        let points = &contour.points;
+        assert_eq!(points.first(), points.last());
+        let turn = |before: Vec2, at: Vec2, after: Vec2| (at - before).angle_between(after - at).abs();
+        let count = points.len();
+        // The wiggles are shorter than the knot spacing: what is left turns like a plain circle
+        let circle_turn = std::f32::consts::TAU / (count - 1) as f32;
+        let seam = turn(points[count - 2], points[0], points[1]);
+        assert!(seam < 1.5 * circle_turn, "turns by {seam} where it closes instead of {circle_turn}");
+        for i in 1..count - 1 {
+            assert!(turn(points[i - 1], points[i], points[i + 1]) < 1.5 * circle_turn);
+        }
+    }
+}
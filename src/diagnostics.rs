@@ -0,0 +1,174 @@
+use crate::barnes_hut::QuadTree;
+use crate::charges::PointCharge;
+use crate::simulation::Simulation;
+use crate::units::Units;
+use macroquad::math::Vec2;
+use std::collections::VecDeque;
+
+/// Conserved quantities of the whole system at a given time, in the units of
+/// the active unit system (joules and kilograms metres per second in SI).
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct EnergyReport {
+    pub time: f32,
+    pub kinetic: f32,
+    pub potential: f32,
+    pub momentum: Vec2,
+}
+
+impl EnergyReport {
+    /// Measures the energy and momentum of `charges`. In crowded scenes the
+    /// potential energy comes from a Barnes–Hut tree with `opening_angle`.
+    #[must_use]
+    pub fn measure(charges: &[PointCharge], units: &Units, time: f32, opening_angle: f32) -> Self {
+        let mut report = EnergyReport { time, ..Default::default() };
+        for charge in charges {
+            let velocity = units.pixels_to_metres(1.0) * charge.velocity;
+            report.kinetic += 0.5 * charge.m * velocity.length_squared();
+            report.momentum += charge.m * velocity;
+        }
+        if charges.len() >= Simulation::BARNES_HUT_MIN_CHARGES {
+            let tree = QuadTree::new(charges.iter().map(|charge| (charge.center, charge.q())), opening_angle);
+            // Every pair is counted from both ends
+            report.potential = 0.5 * charges.iter().enumerate()
+                .map(|(i, charge)| units.energy_in_potential(charge.q(), tree.potential_at(charge.center, units, Some(i), 0.0)))
+                .sum::<f32>();
+        } else {
+            for (i, charge) in charges.iter().enumerate() {
+                for other in &charges[i + 1..] {
+                    report.potential += units.potential_energy(charge.q(), other.q(), charge.center.distance(other.center));
+                }
+            }
+        }
+        report
+    }
+
+    #[must_use]
+    pub fn total(&self) -> f32 {
+        self.kinetic + self.potential
+    }
+}
+
+/// Latest energy report together with a rolling history sampled at a fixed
+/// interval of simulated time.
+pub struct Diagnostics {
+    latest: EnergyReport,
+    history: VecDeque<EnergyReport>,
+    pub capacity: usize,
+    pub sample_interval: f32,
+}
+
+impl Default for Diagnostics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Diagnostics {
+    const DEFAULT_CAPACITY: usize = 600;
+    const DEFAULT_SAMPLE_INTERVAL: f32 = 1.0 / 60.0;
+
+    #[must_use]
+    pub fn new() -> Self {
+        Diagnostics {
+            latest: EnergyReport::default(),
+            history: VecDeque::with_capacity(Self::DEFAULT_CAPACITY),
+            capacity: Self::DEFAULT_CAPACITY,
+            sample_interval: Self::DEFAULT_SAMPLE_INTERVAL,
+        }
+    }
+
+    /// Whether a report for `time` would be added to the history, so that
+    /// callers only measure the system when needed.
+    #[must_use]
+    pub fn is_due(&self, time: f32) -> bool {
+        self.history.back().is_none_or(|last| time - last.time >= self.sample_interval)
+    }
+
+    pub fn record(&mut self, report: EnergyReport) {
+        self.latest = report;
+        if self.is_due(report.time) {
+            if self.history.len() == self.capacity {
+                self.history.pop_front();
+            }
+            self.history.push_back(report);
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.history.clear();
+    }
+
+    #[must_use]
+    pub fn latest(&self) -> &EnergyReport {
+        &self.latest
+    }
+
+    #[must_use]
+    pub fn history(&self) -> &VecDeque<EnergyReport> {
+        &self.history
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn moving_charge(id: usize, center: Vec2, q: f32, m: f32, velocity: Vec2) -> PointCharge {
+        let mut charge = PointCharge::new(id, center, q, false);
+        charge.m = m;
+        charge.velocity = velocity;
+        charge
+    }
+
+    #[test]
+    fn report_sums_kinetic_energy_momentum_and_pair_potentials() {
+        let units = Units::si(100.0);
+        let q = 1e-6;
+        let charges = [
+            moving_charge(0, Vec2::new(0.0, 0.0), q, 2.0, Vec2::new(300.0, 0.0)),
+            moving_charge(1, Vec2::new(100.0, 0.0), -q, 1.0, Vec2::new(0.0, -200.0)),
+        ];
+        let report = EnergyReport::measure(&charges, &units, 1.5, 0.5);
+
+        // 3 m/s and 2 m/s
+        assert_eq!(report.time, 1.5);
+        assert!((report.kinetic - (0.5 * 2.0 * 9.0 + 0.5 * 1.0 * 4.0)).abs() <= 1e-4);
+        assert!((report.momentum - Vec2::new(6.0, -2.0)).length() <= 1e-4);
+        // Opposite charges 1 m apart
+        assert!((report.potential + 8.988e-3).abs() <= 1e-5, "{}", report.potential);
+        assert_eq!(report.total(), report.kinetic + report.potential);
+    }
+
+    #[test]
+    fn crowded_potential_energy_matches_the_sum_over_pairs() {
+        let units = Units::default();
+        let charges: Vec<PointCharge> = (0..Simulation::BARNES_HUT_MIN_CHARGES)
+            .map(|id| {
+                let q = if id % 3 == 0 { -PointCharge::DEFAULT_CHARGE } else { PointCharge::DEFAULT_CHARGE };
+                moving_charge(id, Vec2::new((id % 8) as f32 * 40.0, (id / 8) as f32 * 40.0), q, 1.0, Vec2::ZERO)
+            })
+            .collect();
+        let mut exact = 0.0;
+        for (i, charge) in charges.iter().enumerate() {
+            for other in &charges[i + 1..] {
+                exact += units.potential_energy(charge.q(), other.q(), charge.center.distance(other.center));
+            }
+        }
+        // A zero opening angle makes the tree exact
+        let report = EnergyReport::measure(&charges, &units, 0.0, 0.0);
+        assert!((report.potential - exact).abs() <= 1e-3 * exact.abs(), "{} != {exact}", report.potential);
+    }
+
+    #[test]
+    fn history_is_sampled_at_the_interval_and_capped() {
+        let mut diagnostics = Diagnostics::new();
+        diagnostics.capacity = 3;
+        diagnostics.sample_interval = 1.0;
+        for time in [0.0, 0.5, 1.0, 1.2, 2.0, 3.0, 3.5] {
+            diagnostics.record(EnergyReport { time, ..Default::default() });
+        }
+        let times: Vec<f32> = diagnostics.history().iter().map(|report| report.time).collect();
+        assert_eq!(times, [1.0, 2.0, 3.0]);
+        assert_eq!(diagnostics.latest().time, 3.5);
+    }
+}
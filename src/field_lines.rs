@@ -0,0 +1,155 @@
+use crate::charges::Sign::{Negative, Positive};
+use crate::charges::{coulomb_force, PointCharge};
+use crate::units::Units;
+use macroquad::math::{Rect, Vec2};
+use rayon::prelude::*;
+use std::f32::consts::TAU;
+
+/// A field line as a polyline in world pixels, from its seed next to a source
+/// charge to where it ends.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct FieldLine {
+    pub points: Vec<Vec2>,
+    /// Id of the charge the line ends on, `None` if it leaves the traced region
+    /// or runs out of steps first.
+    pub sink: Option<usize>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct FieldLineSettings {
+    /// Lines leaving a charge of `PointCharge::DEFAULT_CHARGE`; the count scales
+    /// with the magnitude of each charge.
+    pub lines_per_unit_charge: f32,
+    /// Integration step, in world pixels along the line.
+    pub step: f32,
+    pub max_steps: usize,
+}
+
+impl Default for FieldLineSettings {
+    fn default() -> Self {
+        FieldLineSettings {
+            lines_per_unit_charge: Self::DEFAULT_LINES_PER_UNIT_CHARGE,
+            step: Self::DEFAULT_STEP,
+            max_steps: Self::DEFAULT_MAX_STEPS,
+        }
+    }
+}
+
+impl FieldLineSettings {
+    pub const DEFAULT_LINES_PER_UNIT_CHARGE: f32 = 12.0;
+    pub const DEFAULT_STEP: f32 = 2.0;
+    pub const DEFAULT_MAX_STEPS: usize = 2000;
+}
+
+/// Traces field lines with RK4 along the field direction, starting from seeds
+/// evenly spread around every positive charge, until they reach a charge of the opposite sign,
+/// leave `bounds` or run out of steps. Without positive charges, lines are
+/// traced backwards from the negative ones instead.
+#[must_use]
+pub fn trace_field_lines(charges: &[PointCharge], units: &Units, bounds: Rect, settings: &FieldLineSettings) -> Vec<FieldLine> {
+    let has_sources = charges.iter().any(|charge| charge.sign == Positive);
+    let (source_sign, direction) = if has_sources { (Positive, 1.0) } else { (Negative, -1.0) };
+
+    let seeds: Vec<(usize, Vec2)> = charges.iter().enumerate()
+        .filter(|(_, charge)| charge.sign == source_sign)
+        .flat_map(|(i, charge)| {
+            #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+            let count = (settings.lines_per_unit_charge * charge.q().abs() / PointCharge::DEFAULT_CHARGE).round().max(1.0) as usize;
+            let radius = charge.drawing_circle.radius;
+            (0..count).map(move |k| (i, charge.center + radius * Vec2::from_angle(TAU * (k as f32 + 0.5) / count as f32)))
+        })
+        .collect();
+
+    seeds.into_par_iter()
+        .map(|(source, seed)| trace(charges, units, bounds, settings, source, seed, direction))
+        .collect()
+}
+
+/// Unit vector along the field at `point`, reversed when `direction` is negative.
+fn field_direction(point: Vec2, charges: &[PointCharge], units: &Units, direction: f32) -> Vec2 {
+    let field: Vec2 = charges.iter()
+        .map(|charge| coulomb_force(point, 1.0, charge.center, charge.q(), units))
+        .sum();
+    direction * field.normalize_or_zero()
+}
+
+fn trace(charges: &[PointCharge], units: &Units, bounds: Rect, settings: &FieldLineSettings, source: usize, seed: Vec2, direction: f32) -> FieldLine {
+    let tangent = |point: Vec2| field_direction(point, charges, units, direction);
+    let source_sign = charges[source].sign;
+    let h = settings.step;
+    let mut points = vec![seed];
+    let mut point = seed;
+    for _ in 0..settings.max_steps {
+        let k1 = tangent(point);
+        if k1 == Vec2::ZERO {
+            // Null point of the field
+            break;
+        }
+        let k2 = tangent(point + h / 2.0 * k1);
+        let k3 = tangent(point + h / 2.0 * k2);
+        let k4 = tangent(point + h * k3);
+        point += h / 6.0 * (k1 + 2.0 * k2 + 2.0 * k3 + k4);
+        points.push(point);
+
+        if !bounds.contains(point) {
+            break;
+        }
+        // Lines pass by charges of the source's sign and only end on opposite ones
+        if let Some(sink) = charges.iter()
+            .find(|charge| charge.sign != source_sign && charge.drawing_circle.contains(point)) {
+            points.push(sink.center);
+            return FieldLine { points, sink: Some(sink.id) };
+        }
+    }
+    FieldLine { points, sink: None }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BOUNDS: Rect = Rect { x: 0.0, y: 0.0, w: 400.0, h: 400.0 };
+
+    #[test]
+    fn line_from_a_dipole_ends_on_the_opposite_charge() {
+        let charges = vec![
+            PointCharge::new(7, Vec2::new(150.0, 200.0), PointCharge::DEFAULT_CHARGE, false),
+            PointCharge::new(9, Vec2::new(250.0, 200.0), -PointCharge::DEFAULT_CHARGE, false),
+        ];
+        let lines = trace_field_lines(&charges, &Units::default(), BOUNDS, &FieldLineSettings::default());
+
+        // The seed closest to the axis of the dipole heads straight for the negative charge
+        let line = lines.iter()
+            .min_by(|a, b| (a.points[0].y - 200.0).abs().total_cmp(&(b.points[0].y - 200.0).abs()).then(b.points[0].x.total_cmp(&a.points[0].x)))
+            .unwrap();
+        assert_eq!(line.sink, Some(9));
+        assert_eq!(*line.points.last().unwrap(), charges[1].center);
+        assert!(lines.iter().all(|line| line.sink != Some(7)));
+    }
+
+    #[test]
+    fn lines_from_a_lone_charge_reach_the_boundary() {
+        let charges = vec![PointCharge::new(0, Vec2::new(200.0, 200.0), PointCharge::DEFAULT_CHARGE, false)];
+        let lines = trace_field_lines(&charges, &Units::default(), BOUNDS, &FieldLineSettings::default());
+
+        assert!(!lines.is_empty());
+        for line in &lines {
+            assert_eq!(line.sink, None);
+            assert!(!BOUNDS.contains(*line.points.last().unwrap()), "line stopped at {}", line.points.last().unwrap());
+        }
+    }
+
+    #[test]
+    fn lines_pass_by_charges_of_the_same_sign() {
+        // A weak positive charge just off the axis of a dipole, which the axial line grazes
+        let charges = vec![
+            PointCharge::new(0, Vec2::new(150.0, 200.0), PointCharge::DEFAULT_CHARGE, false),
+            PointCharge::new(1, Vec2::new(250.0, 200.0), -PointCharge::DEFAULT_CHARGE, false),
+            PointCharge::new(2, Vec2::new(200.0, 204.0), 0.01 * PointCharge::DEFAULT_CHARGE, false),
+        ];
+        let lines = trace_field_lines(&charges, &Units::default(), BOUNDS, &FieldLineSettings::default());
+
+        assert!(lines.iter().any(|line| line.points.iter().any(|point| charges[2].drawing_circle.contains(*point))));
+        assert!(lines.iter().all(|line| line.sink != Some(0) && line.sink != Some(2)));
+    }
+}
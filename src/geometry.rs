@@ -1,12 +1,10 @@
 
-use std::fmt;
-use macroquad::color::{Color, GREEN, LIGHTGRAY, PURPLE, WHITE};
+use macroquad::color::{Color, WHITE};
 use macroquad::color_u8;
-use macroquad::math::{polar_to_cartesian, Rect, Vec2};
+use macroquad::math::{Rect, Vec2};
 use macroquad::prelude::{draw_circle, draw_line};
 use macroquad::shapes::draw_triangle;
-use macroquad::text::{draw_text, get_text_center};
-use macroquad::window::set_fullscreen;
+use macroquad::text::draw_text;
 use crate::charges::{PointCharge, Sign};
 use crate::Drawable;
 
@@ -52,7 +50,7 @@ pub fn draw_arrow(application_point: Vec2, ending_point: Vec2,  body_size: f32,
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct ChargeCircle {
     pub center: Vec2,
     pub radius: f32,
@@ -74,6 +72,19 @@ impl ChargeCircle {
         }
     }
 
+    #[must_use]
+    pub fn color(&self) -> Color {
+        self.color
+    }
+
+    pub fn set_color(&mut self, color: Color) {
+        self.color = color;
+    }
+
+    pub fn set_fixed(&mut self, is_fixed: bool) {
+        self.is_fixed = is_fixed;
+    }
+
     #[must_use] pub fn contains(&self, point: Vec2) -> bool {
         self.center.distance_squared(point) < self.radius.powi(2)
 
@@ -112,14 +123,14 @@ pub struct ForceArrow {
 
 impl ForceArrow {
     const MAX_ARROW_MAGNITUDE: f32 = PointCharge::DEFAULT_RADIUS * 2.0;
-    pub fn new(application_point: Vec2, rho: f32, max_magnitude:f32, theta: f32, color: Color) -> Self {
-        let raw_magnitude =  rho;
+    pub fn new(application_point: Vec2, force: Vec2, max_magnitude:f32, color: Color) -> Self {
+        let raw_magnitude = force.length();
         let scaled_magnitude = 46.0 + raw_magnitude * Self::MAX_ARROW_MAGNITUDE / max_magnitude;
 
         //dbg!(raw_magnitude, max_magnitude, scaled_magnitude);
         // assert!(max_magnitude >= raw_magnitude, "{max_magnitude} < {raw_magnitude}");
         // assert!(scaled_magnitude <= Self::MAX_ARROW_MAGNITUDE+ 10.0, "{scaled_magnitude} > {}", Self::MAX_ARROW_MAGNITUDE + 10.0); // +10.0 for tolerance
-        let ending_point = (polar_to_cartesian(scaled_magnitude.min(Self::MAX_ARROW_MAGNITUDE), theta) + application_point);
+        let ending_point = force.normalize_or_zero() * scaled_magnitude.min(Self::MAX_ARROW_MAGNITUDE) + application_point;
         ForceArrow { application_point, ending_point, color }
     }
 
@@ -141,34 +152,40 @@ pub struct FieldArrow {
 
 impl FieldArrow {
     const MAX_RHO: f32= 20.0;
-    pub fn new(application_point: Vec2, rho: f32, max_magnitude:f32, theta: f32, potential: f32) -> Self {
-        let mut ending_point= application_point;
-        let color_intensity =  (30 +f32::round((rho * 255.0) / max_magnitude) as u16).min(255) as u8;
-
-        let color = color_u8!(color_intensity, color_intensity, color_intensity, 255);
-
-        if (rho > 0.0) {
-            ending_point = polar_to_cartesian(rho.min(Self::MAX_RHO),theta ) + application_point;
-        }
+    pub fn new(application_point: Vec2, field: Vec2, max_magnitude:f32, _potential: f32) -> Self {
+        let color = Self::color_for(field, max_magnitude);
+        let ending_point = Self::ending_point_for(application_point, field);
 
         FieldArrow {
-            application_point: application_point,
-            ending_point: ending_point,
-            color: color
+            application_point,
+            ending_point,
+            color
         }
     }
 
 
 
-    pub fn update(&mut self, rho: f32, max_magnitude: f32, theta: f32, potential: f32) {
-        let mut ending_point= self.application_point;
-        let color_intensity =  (30 +f32::round((rho * 255.0) / max_magnitude) as u16).min(255) as u8;
+    pub fn update(&mut self, field: Vec2, max_magnitude: f32, _potential: f32) {
         // let color = Self::calculate_color_by_potential(potential, color_intensity);
-        if (rho > 0.0) {
-            ending_point = polar_to_cartesian(rho.min(Self::MAX_RHO),theta ) + self.application_point;
-        }
-        self.color = color_u8!(color_intensity, color_intensity, color_intensity, 255);;
-        self.ending_point = ending_point;
+        self.color = Self::color_for(field, max_magnitude);
+        self.ending_point = Self::ending_point_for(self.application_point, field);
+    }
+
+    /// Draws the arrow starting from `application_point` instead of its own,
+    /// keeping its length and direction.
+    pub fn draw_at(&self, application_point: Vec2) {
+        draw_arrow(application_point, application_point + self.ending_point - self.application_point, 2.5, 7.5, self.color);
+    }
+
+    /// Brightness encodes the field strength relative to the strongest arrow.
+    fn color_for(field: Vec2, max_magnitude: f32) -> Color {
+        let color_intensity =  (30 +f32::round((field.length() * 255.0) / max_magnitude) as u16).min(255) as u8;
+        color_u8!(color_intensity, color_intensity, color_intensity, 255)
+    }
+
+    /// Every arrow has the same length, independently of the unit system.
+    fn ending_point_for(application_point: Vec2, field: Vec2) -> Vec2 {
+        field.normalize_or_zero() * Self::MAX_RHO + application_point
     }
 }
 
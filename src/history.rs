@@ -0,0 +1,234 @@
+use crate::charges::PointCharge;
+use crate::simulation::Simulation;
+use macroquad::color::Color;
+use macroquad::math::Vec2;
+use std::collections::VecDeque;
+
+/// Editable properties of a charge, as changed by the inspector and the
+/// editing shortcuts.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ChargeProperties {
+    pub q: f32,
+    pub m: f32,
+    pub is_fixed: bool,
+    pub color: Option<Color>,
+}
+
+impl ChargeProperties {
+    #[must_use]
+    pub fn capture(charge: &PointCharge) -> Self {
+        ChargeProperties {
+            q: charge.q(),
+            m: charge.m,
+            is_fixed: charge.is_fixed,
+            color: charge.color(),
+        }
+    }
+
+    pub fn apply(&self, charge: &mut PointCharge) {
+        charge.set_color(self.color);
+        charge.set_charge(self.q);
+        charge.m = self.m;
+        charge.set_fixed(self.is_fixed);
+    }
+}
+
+/// A reversible change to the scene made by the user. Changes made by the
+/// simulation itself, such as merges, are not edits.
+#[derive(Clone, Debug)]
+pub enum Edit {
+    Spawn(PointCharge),
+    Delete(PointCharge),
+    Move { id: usize, from: Vec2, to: Vec2 },
+    Change { id: usize, before: ChargeProperties, after: ChargeProperties },
+    ClearEquipotentials(Vec<f32>),
+}
+
+impl Edit {
+    /// Performs the edit again, after it was undone.
+    pub fn apply(&self, simulation: &mut Simulation, equipotentials: &mut Vec<f32>) {
+        match self {
+            Edit::Spawn(charge) => simulation.add_charge(charge.clone()),
+            Edit::Delete(charge) => {
+                simulation.remove_charge(charge.id);
+            }
+            Edit::Move { id, to, .. } => move_charge(simulation, *id, *to),
+            Edit::Change { id, after, .. } => change_charge(simulation, *id, after),
+            Edit::ClearEquipotentials(_) => equipotentials.clear(),
+        }
+    }
+
+    /// Reverts the edit.
+    pub fn revert(&self, simulation: &mut Simulation, equipotentials: &mut Vec<f32>) {
+        match self {
+            Edit::Spawn(charge) => {
+                simulation.remove_charge(charge.id);
+            }
+            Edit::Delete(charge) => simulation.add_charge(charge.clone()),
+            Edit::Move { id, from, .. } => move_charge(simulation, *id, *from),
+            Edit::Change { id, before, .. } => change_charge(simulation, *id, before),
+            Edit::ClearEquipotentials(cleared) => equipotentials.clone_from(cleared),
+        }
+    }
+}
+
+fn move_charge(simulation: &mut Simulation, id: usize, center: Vec2) {
+    if let Some(charge) = simulation.charge_by_id_mut(id) {
+        charge.set_center(center);
+        charge.velocity = Vec2::ZERO;
+    }
+}
+
+fn change_charge(simulation: &mut Simulation, id: usize, properties: &ChargeProperties) {
+    if let Some(charge) = simulation.charge_by_id_mut(id) {
+        properties.apply(charge);
+    }
+}
+
+/// Undo and redo stacks of edits; the oldest edits are forgotten beyond `capacity`.
+pub struct History {
+    undo: VecDeque<Edit>,
+    redo: Vec<Edit>,
+    pub capacity: usize,
+}
+
+impl Default for History {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl History {
+    const DEFAULT_CAPACITY: usize = 200;
+
+    #[must_use]
+    pub fn new() -> Self {
+        History { undo: VecDeque::new(), redo: vec![], capacity: Self::DEFAULT_CAPACITY }
+    }
+
+    /// Records an edit that was just performed, discarding anything that could be redone.
+    pub fn record(&mut self, edit: Edit) {
+        self.redo.clear();
+        self.push_undo(edit);
+    }
+
+    /// Adds an edit that can be undone, forgetting the oldest ones beyond `capacity`.
+    fn push_undo(&mut self, edit: Edit) {
+        self.undo.push_back(edit);
+        while self.undo.len() > self.capacity {
+            self.undo.pop_front();
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.undo.clear();
+        self.redo.clear();
+    }
+
+    /// Reverts the latest edit, returning whether there was one.
+    pub fn undo(&mut self, simulation: &mut Simulation, equipotentials: &mut Vec<f32>) -> bool {
+        let Some(edit) = self.undo.pop_back() else {
+            return false;
+        };
+        edit.revert(simulation, equipotentials);
+        self.redo.push(edit);
+        true
+    }
+
+    /// Performs again the latest undone edit, returning whether there was one.
+    pub fn redo(&mut self, simulation: &mut Simulation, equipotentials: &mut Vec<f32>) -> bool {
+        let Some(edit) = self.redo.pop() else {
+            return false;
+        };
+        edit.apply(simulation, equipotentials);
+        self.push_undo(edit);
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn spawn(simulation: &mut Simulation, history: &mut History, x: f32) -> usize {
+        let id = simulation.spawn_charge(Vec2::new(x, 0.0), PointCharge::DEFAULT_CHARGE, false);
+        history.record(Edit::Spawn(simulation.charge_by_id(id).unwrap().clone()));
+        id
+    }
+
+    /// Undoes every edit, returning how many there were.
+    fn undo_all(simulation: &mut Simulation, history: &mut History) -> usize {
+        let mut count = 0;
+        while history.undo(simulation, &mut vec![]) {
+            count += 1;
+        }
+        count
+    }
+
+    #[test]
+    fn edits_are_undone_and_redone_in_order() {
+        let mut simulation = Simulation::new(100, 100);
+        let mut history = History::new();
+        let id = spawn(&mut simulation, &mut history, 10.0);
+        history.record(Edit::Move { id, from: Vec2::new(10.0, 0.0), to: Vec2::new(50.0, 0.0) });
+        simulation.charge_by_id_mut(id).unwrap().set_center(Vec2::new(50.0, 0.0));
+
+        assert!(history.undo(&mut simulation, &mut vec![]));
+        assert_eq!(simulation.charge_by_id(id).unwrap().center, Vec2::new(10.0, 0.0));
+        assert!(history.undo(&mut simulation, &mut vec![]));
+        assert!(simulation.charges.is_empty());
+        assert!(!history.undo(&mut simulation, &mut vec![]));
+
+        assert!(history.redo(&mut simulation, &mut vec![]));
+        assert!(history.redo(&mut simulation, &mut vec![]));
+        assert_eq!(simulation.charge_by_id(id).unwrap().center, Vec2::new(50.0, 0.0));
+        assert!(!history.redo(&mut simulation, &mut vec![]));
+    }
+
+    #[test]
+    fn recording_discards_what_could_be_redone() {
+        let mut simulation = Simulation::new(100, 100);
+        let mut history = History::new();
+        spawn(&mut simulation, &mut history, 10.0);
+        history.undo(&mut simulation, &mut vec![]);
+        spawn(&mut simulation, &mut history, 20.0);
+        assert!(!history.redo(&mut simulation, &mut vec![]));
+    }
+
+    #[test]
+    fn clearing_equipotentials_is_undone() {
+        let mut simulation = Simulation::new(100, 100);
+        let mut history = History::new();
+        let mut equipotentials = vec![1.0, 2.0];
+        history.record(Edit::ClearEquipotentials(equipotentials.clone()));
+        equipotentials.clear();
+        history.undo(&mut simulation, &mut equipotentials);
+        assert_eq!(equipotentials, [1.0, 2.0]);
+        history.redo(&mut simulation, &mut equipotentials);
+        assert!(equipotentials.is_empty());
+    }
+
+    #[test]
+    fn oldest_edits_are_forgotten_beyond_the_capacity() {
+        let mut simulation = Simulation::new(100, 100);
+        let mut history = History { capacity: 3, ..History::new() };
+        for i in 0..5 {
+            spawn(&mut simulation, &mut history, 10.0 * i as f32);
+        }
+        assert_eq!(undo_all(&mut simulation, &mut history), 3);
+        assert_eq!(simulation.charges.len(), 2);
+    }
+
+    #[test]
+    fn redoing_keeps_within_the_capacity() {
+        let mut simulation = Simulation::new(100, 100);
+        let mut history = History::new();
+        for i in 0..4 {
+            spawn(&mut simulation, &mut history, 10.0 * i as f32);
+        }
+        history.undo(&mut simulation, &mut vec![]);
+        history.capacity = 2;
+        history.redo(&mut simulation, &mut vec![]);
+        assert_eq!(undo_all(&mut simulation, &mut history), 2);
+    }
+}
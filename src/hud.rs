@@ -0,0 +1,153 @@
+use crate::camera::Camera;
+use crate::charges::PointCharge;
+use crate::colormap::ColorScale;
+use crate::diagnostics::{Diagnostics, EnergyReport};
+use macroquad::color::{Color, GREEN, ORANGE, SKYBLUE, WHITE};
+use macroquad::color_u8;
+use macroquad::math::{Rect, Vec2};
+use macroquad::shapes::{draw_line, draw_rectangle, draw_rectangle_lines};
+use macroquad::text::{draw_text, measure_text};
+
+const PANEL_BACKGROUND: Color = color_u8!(0, 0, 0, 180);
+const PANEL_PADDING: f32 = 8.0;
+const PANEL_FONT_SIZE: f32 = 16.0;
+const PANEL_LINE_HEIGHT: f32 = 16.0;
+
+/// One plotted quantity: label, color and how to read it from a report.
+struct Series {
+    label: &'static str,
+    color: Color,
+    value: fn(&EnergyReport) -> f32,
+}
+
+const ENERGY_SERIES: [Series; 3] = [
+    Series { label: "Kinetic", color: ORANGE, value: |report| report.kinetic },
+    Series { label: "Potential", color: SKYBLUE, value: |report| report.potential },
+    Series { label: "Total", color: WHITE, value: EnergyReport::total },
+];
+
+const MOMENTUM_SERIES: [Series; 1] = [
+    Series { label: "|Momentum|", color: GREEN, value: |report| report.momentum.length() },
+];
+
+/// Draws the latest energy and momentum values over rolling time-series plots.
+pub fn draw_diagnostics_panel(diagnostics: &Diagnostics, area: Rect) {
+    draw_rectangle(area.x, area.y, area.w, area.h, PANEL_BACKGROUND);
+    draw_rectangle_lines(area.x, area.y, area.w, area.h, 2.0, WHITE);
+
+    let latest = diagnostics.latest();
+    let lines = [
+        format!("Kinetic: {:.4e}", latest.kinetic),
+        format!("Potential: {:.4e}", latest.potential),
+        format!("Total: {:.4e}", latest.total()),
+        format!("Momentum: ({:.3e}, {:.3e})", latest.momentum.x, latest.momentum.y),
+    ];
+    let mut y = area.y + PANEL_PADDING + PANEL_LINE_HEIGHT;
+    for line in &lines {
+        draw_text(line, area.x + PANEL_PADDING, y, PANEL_FONT_SIZE, WHITE);
+        y += PANEL_LINE_HEIGHT;
+    }
+
+    let plots_top = y;
+    let plot_height = (area.y + area.h - plots_top - 2.0 * PANEL_PADDING) / 2.0;
+    let plot_width = area.w - 2.0 * PANEL_PADDING;
+    draw_plot(diagnostics, &ENERGY_SERIES, Rect::new(area.x + PANEL_PADDING, plots_top, plot_width, plot_height));
+    draw_plot(diagnostics, &MOMENTUM_SERIES, Rect::new(area.x + PANEL_PADDING, plots_top + plot_height + PANEL_PADDING, plot_width, plot_height));
+}
+
+/// Plots every series over the whole history, sharing one vertical range.
+fn draw_plot(diagnostics: &Diagnostics, series: &[Series], area: Rect) {
+    draw_rectangle_lines(area.x, area.y, area.w, area.h, 1.0, color_u8!(255, 255, 255, 120));
+
+    let history = diagnostics.history();
+    let (min, max) = history.iter()
+        .flat_map(|report| series.iter().map(move |series| (series.value)(report)))
+        .fold((f32::INFINITY, f32::NEG_INFINITY), |(min, max), value| (min.min(value), max.max(value)));
+    if history.len() < 2 || !min.is_finite() || !max.is_finite() {
+        return;
+    }
+    // Keep flat lines in the middle instead of dividing by zero
+    let (min, range) = if max - min > f32::EPSILON * max.abs().max(1.0) { (min, max - min) } else { (min - 0.5, 1.0) };
+    let to_screen = |index: usize, value: f32| Vec2::new(
+        area.x + area.w * index as f32 / (diagnostics.capacity - 1) as f32,
+        area.y + area.h - area.h * (value - min) / range,
+    );
+
+    for (i, series) in series.iter().enumerate() {
+        let mut previous: Option<Vec2> = None;
+        for (index, report) in history.iter().enumerate() {
+            let point = to_screen(index, (series.value)(report));
+            if let Some(previous) = previous {
+                draw_line(previous.x, previous.y, point.x, point.y, 1.5, series.color);
+            }
+            previous = Some(point);
+        }
+        draw_text(series.label, area.x + 4.0 + i as f32 * area.w / 3.0, area.y + PANEL_FONT_SIZE, PANEL_FONT_SIZE, series.color);
+    }
+}
+
+const INSPECTOR_SIZE: Vec2 = Vec2::new(230.0, 118.0);
+const INSPECTOR_OFFSET: f32 = 12.0;
+
+/// Draws a popup next to `charge` listing its editable properties and the keys editing them.
+pub fn draw_inspector(charge: &PointCharge, camera: &Camera) {
+    let center = camera.world_to_screen(charge.center);
+    let area = Rect::new(
+        center.x + charge.drawing_circle.radius * camera.zoom + INSPECTOR_OFFSET,
+        center.y - INSPECTOR_SIZE.y / 2.0,
+        INSPECTOR_SIZE.x,
+        INSPECTOR_SIZE.y,
+    );
+    draw_rectangle(area.x, area.y, area.w, area.h, PANEL_BACKGROUND);
+    draw_rectangle_lines(area.x, area.y, area.w, area.h, 2.0, WHITE);
+
+    let lines = [
+        format!("Charge #{}", charge.id),
+        format!("q = {:+.3e} C", charge.q()),
+        format!("m = {:.3e} kg", charge.m),
+        format!("Fixed: {}", if charge.is_fixed { "yes" } else { "no" }),
+        "Up/Down: charge, Left/Right: mass".to_owned(),
+        "F: fix, S: flip, D: copy, Del: delete".to_owned(),
+    ];
+    let mut y = area.y + PANEL_PADDING + PANEL_LINE_HEIGHT;
+    for line in &lines {
+        draw_text(line, area.x + PANEL_PADDING, y, PANEL_FONT_SIZE, WHITE);
+        y += PANEL_LINE_HEIGHT;
+    }
+}
+
+// Heights of the colorbar ticks, as fractions of the bar
+const COLORBAR_TICKS: [f32; 5] = [1.0, 0.75, 0.5, 0.25, 0.0];
+
+/// Draws the color scale as a vertical bar filling `area`, largest values on
+/// top, with ticks in `unit` on its left.
+pub fn draw_colorbar(scale: &ColorScale, area: Rect, unit: &str) {
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    let rows = area.h.max(1.0) as usize;
+    for row in 0..rows {
+        let fraction = 1.0 - (row as f32 + 0.5) / rows as f32;
+        draw_rectangle(area.x, area.y + row as f32, area.w, 1.0, scale.color(scale.value_at(fraction)));
+    }
+    draw_rectangle_lines(area.x, area.y, area.w, area.h, 1.0, WHITE);
+
+    for fraction in COLORBAR_TICKS {
+        let y = area.y + area.h * (1.0 - fraction);
+        let label = format_quantity(scale.value_at(fraction), unit);
+        let width = measure_text(&label, None, PANEL_FONT_SIZE as u16, 1.0).width;
+        draw_line(area.x - 4.0, y, area.x, y, 1.0, WHITE);
+        draw_text(&label, area.x - 6.0 - width, y + PANEL_FONT_SIZE / 3.0, PANEL_FONT_SIZE, WHITE);
+    }
+    let title = scale.scaling.name();
+    let width = measure_text(title, None, PANEL_FONT_SIZE as u16, 1.0).width;
+    draw_text(title, area.x + (area.w - width) / 2.0, area.y + area.h + PANEL_LINE_HEIGHT, PANEL_FONT_SIZE, WHITE);
+}
+
+/// A few decimals, in scientific notation when far from unity.
+fn format_quantity(value: f32, unit: &str) -> String {
+    let magnitude = value.abs();
+    if magnitude != 0.0 && !(0.01..1000.0).contains(&magnitude) {
+        format!("{value:.2e} {unit}")
+    } else {
+        format!("{value:.3} {unit}")
+    }
+}
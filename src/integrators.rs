@@ -0,0 +1,152 @@
+use crate::Cycle;
+use macroquad::math::Vec2;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// Position and velocity of a body, in pixels and pixels per second.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct PhaseState {
+    pub position: Vec2,
+    pub velocity: Vec2,
+}
+
+/// Maps the states of all bodies to their accelerations, in pixels per second squared.
+pub type AccelerationFn<'a> = dyn Fn(&[PhaseState]) -> Vec<Vec2> + 'a;
+
+/// Numerical scheme advancing a system of bodies in time.
+pub trait Integrator {
+    fn name(&self) -> &'static str;
+
+    /// Advances every state by `dt` seconds.
+    fn integrate(&self, states: &mut [PhaseState], dt: f32, acceleration: &AccelerationFn);
+}
+
+/// First-order, updates positions with the old velocities. Gains energy over time.
+pub struct ExplicitEuler;
+
+/// First-order and symplectic: updates velocities first, then positions with the new ones.
+pub struct SemiImplicitEuler;
+
+/// Second-order and symplectic: good long-term energy behaviour for two force evaluations per step.
+/// Energy is only conserved with the damping turned off, which the simulation enables by default.
+pub struct VelocityVerlet;
+
+/// Classic fourth-order Runge-Kutta: very accurate per step, four force evaluations.
+pub struct RungeKutta4;
+
+impl Integrator for ExplicitEuler {
+    fn name(&self) -> &'static str {
+        "Explicit Euler"
+    }
+
+    fn integrate(&self, states: &mut [PhaseState], dt: f32, acceleration: &AccelerationFn) {
+        let accelerations = acceleration(states);
+        for (state, acceleration) in states.iter_mut().zip(accelerations) {
+            state.position += state.velocity * dt;
+            state.velocity += acceleration * dt;
+        }
+    }
+}
+
+impl Integrator for SemiImplicitEuler {
+    fn name(&self) -> &'static str {
+        "Semi-implicit Euler"
+    }
+
+    fn integrate(&self, states: &mut [PhaseState], dt: f32, acceleration: &AccelerationFn) {
+        let accelerations = acceleration(states);
+        for (state, acceleration) in states.iter_mut().zip(accelerations) {
+            state.velocity += acceleration * dt;
+            state.position += state.velocity * dt;
+        }
+    }
+}
+
+impl Integrator for VelocityVerlet {
+    fn name(&self) -> &'static str {
+        "Velocity Verlet"
+    }
+
+    fn integrate(&self, states: &mut [PhaseState], dt: f32, acceleration: &AccelerationFn) {
+        let old_accelerations = acceleration(states);
+        let old_velocities: Vec<Vec2> = states.iter().map(|state| state.velocity).collect();
+        for (state, acceleration) in states.iter_mut().zip(&old_accelerations) {
+            state.position += state.velocity * dt + 0.5 * *acceleration * dt * dt;
+            // Predicted velocity, only used by velocity-dependent accelerations such as damping
+            state.velocity += *acceleration * dt;
+        }
+
+        let new_accelerations = acceleration(states);
+        for ((state, old_velocity), (old_acceleration, new_acceleration)) in states.iter_mut()
+            .zip(old_velocities)
+            .zip(old_accelerations.into_iter().zip(new_accelerations)) {
+            state.velocity = old_velocity + 0.5 * (old_acceleration + new_acceleration) * dt;
+        }
+    }
+}
+
+impl Integrator for RungeKutta4 {
+    fn name(&self) -> &'static str {
+        "Runge-Kutta 4"
+    }
+
+    fn integrate(&self, states: &mut [PhaseState], dt: f32, acceleration: &AccelerationFn) {
+        // Each stage holds the derivative (velocity, acceleration) of every state
+        let derivative = |states: &[PhaseState]| -> Vec<(Vec2, Vec2)> {
+            states.iter().map(|state| state.velocity).zip(acceleration(states)).collect()
+        };
+        let offset = |stage: &[(Vec2, Vec2)], factor: f32| -> Vec<PhaseState> {
+            states.iter().zip(stage).map(|(state, (velocity, acceleration))| PhaseState {
+                position: state.position + *velocity * factor,
+                velocity: state.velocity + *acceleration * factor,
+            }).collect()
+        };
+
+        let k1 = derivative(states);
+        let k2 = derivative(&offset(&k1, dt / 2.0));
+        let k3 = derivative(&offset(&k2, dt / 2.0));
+        let k4 = derivative(&offset(&k3, dt));
+
+        for (i, state) in states.iter_mut().enumerate() {
+            state.position += dt / 6.0 * (k1[i].0 + 2.0 * k2[i].0 + 2.0 * k3[i].0 + k4[i].0);
+            state.velocity += dt / 6.0 * (k1[i].1 + 2.0 * k2[i].1 + 2.0 * k3[i].1 + k4[i].1);
+        }
+    }
+}
+
+/// Integration scheme used by the simulation, saved with scenes.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum IntegratorKind {
+    ExplicitEuler,
+    #[default]
+    SemiImplicitEuler,
+    VelocityVerlet,
+    RungeKutta4,
+}
+
+impl IntegratorKind {
+    #[must_use]
+    pub fn integrator(self) -> &'static dyn Integrator {
+        match self {
+            IntegratorKind::ExplicitEuler => &ExplicitEuler,
+            IntegratorKind::SemiImplicitEuler => &SemiImplicitEuler,
+            IntegratorKind::VelocityVerlet => &VelocityVerlet,
+            IntegratorKind::RungeKutta4 => &RungeKutta4,
+        }
+    }
+}
+
+impl Cycle for IntegratorKind {
+    const ALL: &'static [Self] = &[
+        IntegratorKind::ExplicitEuler,
+        IntegratorKind::SemiImplicitEuler,
+        IntegratorKind::VelocityVerlet,
+        IntegratorKind::RungeKutta4,
+    ];
+}
+
+impl fmt::Display for IntegratorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.integrator().name())
+    }
+}
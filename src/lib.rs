@@ -1,42 +1,38 @@
+pub mod camera;
 pub mod geometry;
 pub mod charges;
-pub mod voltmeter; 
+pub mod barnes_hut;
+pub mod collisions;
+pub mod voltmeter;
+pub mod field_lines;
+pub mod contours;
+pub mod colormap;
+pub mod overlay;
+pub mod simulation;
+pub mod integrators;
+pub mod timestep;
+pub mod diagnostics;
+pub mod hud;
+pub mod scene;
+pub mod presets;
+pub mod quality;
+pub mod history;
+pub mod species;
+pub mod units;
 
 pub trait Drawable {
     fn draw(&self);
 }
 
+/// A setting with a fixed list of choices, stepped through with a key.
+pub trait Cycle: Copy + PartialEq + 'static {
+    /// The choices, in the order they are stepped through.
+    const ALL: &'static [Self];
 
-type ImplIteratorMut<'a, Item> =
-std::iter::Chain<
-    std::slice::IterMut<'a, Item>,
-    std::slice::IterMut<'a, Item>,
->
-;
-pub trait SplitOneMut {
-    type Item;
-
-    fn split_one_mut (
-        &'_ mut self,
-        i: usize,
-    ) -> (&'_ mut Self::Item, ImplIteratorMut<'_, Self::Item>);
-}
-
-impl<T> SplitOneMut for [T] {
-    type Item = T;
-
-    fn split_one_mut (
-        &'_ mut self,
-        i: usize,
-    ) -> (&'_ mut Self::Item, ImplIteratorMut<'_, Self::Item>)
-    {
-        let (prev, current_and_end) = self.split_at_mut(i);
-        let (current, end) = current_and_end.split_at_mut(1);
-        (
-            &mut current[0],
-            prev.iter_mut().chain(end),
-        )
+    /// The choice after this one, wrapping around to the first.
+    #[must_use]
+    fn next(self) -> Self {
+        let index = Self::ALL.iter().position(|choice| *choice == self).unwrap_or(0);
+        Self::ALL[(index + 1) % Self::ALL.len()]
     }
 }
-
-
@@ -1,35 +1,71 @@
 use crate::SimulationState::{Paused, Running};
-use is_close::{is_close, AVERAGE};
-use itertools::Itertools;
-use macroquad::math::f32;
 use macroquad::prelude::*;
 use ndarray::parallel::prelude::*;
-use ndarray::prelude::*;
-use ndarray::{Array, OwnedRepr};
+use ndarray::{Array2, Zip};
+use point_charge_simulation::camera::Camera;
 use point_charge_simulation::charges::Sign::Neutral;
-use point_charge_simulation::charges::{color_based_on_potential, PointCharge, TestCharge};
+use point_charge_simulation::charges::{PointCharge, TestCharge};
+use point_charge_simulation::colormap::ColorScale;
+use point_charge_simulation::contours::{extract_contours, AutoLevels, Contour};
+use point_charge_simulation::field_lines::{trace_field_lines, FieldLine, FieldLineSettings};
+use point_charge_simulation::overlay::{FieldOverlay, OverlayMode};
+use point_charge_simulation::history::{ChargeProperties, Edit, History};
+use point_charge_simulation::hud::{draw_colorbar, draw_diagnostics_panel, draw_inspector};
+use point_charge_simulation::presets::Preset;
+use point_charge_simulation::quality::AutoQuality;
+use point_charge_simulation::scene::Scene;
+use point_charge_simulation::simulation::Simulation;
+use point_charge_simulation::species::SpeciesRegistry;
+use point_charge_simulation::timestep::FixedTimestep;
 use point_charge_simulation::voltmeter::Voltmeter;
+use point_charge_simulation::Cycle;
 use std::default::Default;
-use std::vec;
 use macroquad::miniquad::CursorIcon;
 
 const WINDOW_WIDTH: u16 = 800;
 const WINDOW_HEIGHT: u16 = 500;
 
-const ELECTRIC_FIELD_DENSITY: usize = 25;
-const POTENTIAL_DENSITY: usize = 1;
-const PADDING_FROM_WINDOW_BORDERS: u16 = 0;
 
+// Offset of the HUD's last line from the bottom-left corner of the window
+const HUD_BOTTOM_LEFT_OFFSET: Vec2 = Vec2::new(10.0, -12.0);
+const HUD_FONT_SIZE: f32 = 20.0;
+const HUD_LINE_HEIGHT: f32 = 22.0;
 
+const FIELD_LINE_COLOR: Color = color_u8!(255, 230, 120, 200);
+
+// Spacing of the spline knots smoothing the equipotentials, in screen pixels
+const CONTOUR_KNOT_SPACING: f32 = 12.0;
+const CONTOUR_LABEL_FONT_SIZE: u16 = 16;
+const AUTO_EQUIPOTENTIAL_COLOR: Color = color_u8!(120, 220, 255, 220);
+
+// Zoom factor per mouse-wheel notch
+const ZOOM_STEP: f32 = 1.1;
+// Arrow-key panning speed, in screen pixels per second
+const PAN_SPEED: f32 = 400.0;
+// Right-button movement, in screen pixels, past which a click becomes a pan
+const PAN_DRAG_THRESHOLD: f32 = 4.0;
+
+// The colorbar hangs below the pause indicator, from the right edge of the window
+const COLORBAR_RIGHT_OFFSET: f32 = -36.0;
+const COLORBAR_TOP: f32 = 50.0;
+const COLORBAR_SIZE: Vec2 = Vec2::new(16.0, 200.0);
+// Leaves room for the tick labels of the colorbar on the right
+const COLORBAR_SPACING: f32 = -110.0;
+
+const FIELD_OVERLAY_ALPHA: f32 = 0.6;
+
+const DIAGNOSTICS_PANEL_AREA: Rect = Rect::new(10.0, 30.0, 320.0, 240.0);
+
+// The pause indicator is placed relative to the top-right corner of the window
 const RUNNING_SIMULATION_TRIANGLE_VERTICES: (Vec2, Vec2, Vec2) = (
-    Vec2::new(WINDOW_WIDTH as f32 - 10.0, 20.0),  // Left vertex
-    Vec2::new(WINDOW_WIDTH as f32 - 30.0, 10.0),  // Top right
-    Vec2::new(WINDOW_WIDTH as f32 - 30.0,  30.0),  // Bottom right
+    Vec2::new(-10.0, 20.0),  // Left vertex
+    Vec2::new(-30.0, 10.0),  // Top right
+    Vec2::new(-30.0,  30.0),  // Bottom right
 );
 
  const PAUSED_SIMULATION_RECTANGLES: (Rect, Rect) = (
-     Rect::new(WINDOW_WIDTH as f32 - 30.0, 10.0, 7.0, 20.0),  // Left rectangle
-     Rect::new(WINDOW_WIDTH as f32 - 17.0, 10.0, 7.0, 20.0)   // Right rectangle
+     Rect::new(-30.0, 10.0, 7.0, 20.0),  // Left rectangle
+     Rect::new(-17.0, 10.0, 7.0, 20.0)   // Right rectangle
      );
 
 fn window_conf() -> Conf {
@@ -37,7 +73,9 @@ fn window_conf() -> Conf {
         window_title: "Point charge simulation".to_owned(),
         window_width: i32::from(WINDOW_WIDTH),
         window_height: i32::from(WINDOW_HEIGHT),
-        window_resizable: false,
+        window_resizable: true,
+        // Anti-aliases the field lines and equipotentials
+        sample_count: 4,
         ..Default::default()
     }
 }
@@ -49,134 +87,376 @@ enum SimulationState {
 }
 
 
-#[allow(clippy::similar_names)]
 #[macroquad::main(window_conf)]
 async fn main() {
-
-    // dbg!(TRANSPARENT_COLOR_SLICE.len());
-    let mut charges: Vec<PointCharge> = vec![];
+    let mut simulation = Simulation::new(WINDOW_WIDTH, WINDOW_HEIGHT);
+    let mut camera = Camera::new(Vec2::new(f32::from(WINDOW_WIDTH), f32::from(WINDOW_HEIGHT)));
     let mut simulation_state: SimulationState = Running;
 
-    let field_x_points = (PADDING_FROM_WINDOW_BORDERS..=WINDOW_WIDTH - PADDING_FROM_WINDOW_BORDERS).step_by(ELECTRIC_FIELD_DENSITY);
-    let field_y_points = (PADDING_FROM_WINDOW_BORDERS..=WINDOW_HEIGHT - PADDING_FROM_WINDOW_BORDERS).step_by(ELECTRIC_FIELD_DENSITY);
-    let field_xy_meshgrid = field_x_points.cartesian_product(field_y_points);
-
-    let mut test_charges: Vec<TestCharge> = vec![];
-    for (x,y) in field_xy_meshgrid {
-      test_charges.push(TestCharge::new(Vec2::new(f32::from(x), f32::from(y))));
-    }
-
-
-    let potential_x_points = (0..WINDOW_WIDTH).step_by(POTENTIAL_DENSITY);
-    let potential_y_points = (0..WINDOW_HEIGHT).step_by(POTENTIAL_DENSITY);
-    let potential_xy_meshgrid = potential_x_points.cartesian_product(potential_y_points);
-    let mut max_potential: f32 = 0.0;
-    let mut potential_image = Image::gen_image_color(WINDOW_WIDTH, WINDOW_HEIGHT, BLACK);
+    let (mut potential_image, mut potential_texture) = new_heatmap(simulation.grid_size());
+    let (mut field_image, mut field_texture) = new_heatmap(simulation.grid_size());
+    // Settings the heatmaps were last colored with, `None` when they must be recolored anyway
+    let mut potential_heatmap_scale: Option<ColorScale> = None;
+    let mut field_heatmap_overlay: Option<FieldOverlay> = None;
+    // Field lines last traced, `None` when they must be traced again
+    let mut field_lines: Option<Vec<FieldLine>> = None;
+    // Levels the automatic and the voltmeter equipotentials were last traced at, with the contours
+    let mut auto_equipotentials: Option<(Vec<f32>, Vec<Contour>)> = None;
+    let mut voltmeter_equipotentials: Option<(Vec<f32>, Vec<Contour>)> = None;
 
-    let transparent_equipotential_lines: Image = Image::gen_image_color(WINDOW_WIDTH, WINDOW_HEIGHT, color_u8!(255,255,255, 0));
-    let mut equipotential_lines_image = Image::gen_image_color(WINDOW_WIDTH , WINDOW_HEIGHT , color_u8!(255,255,255, 0));
-    let mut potentials_array = Array::<(Vec2, f32), Ix2>::from_elem((WINDOW_WIDTH as usize,WINDOW_HEIGHT as usize), (Vec2::ZERO, 0.0f32));
-    for (x, y) in potential_xy_meshgrid {
-        potentials_array[[x as usize,y as usize]] = (Vec2::new(f32::from(x), f32::from(y)), 0.0);
-    }
     let mut voltmeter: Voltmeter = Voltmeter::new();
-
-
-    let mut cursor_is_over_a_charge: bool = false;
-    let mut dragging_charge: Option<usize> = None;
+    let mut species_registry = SpeciesRegistry::new();
+    let mut show_diagnostics = false;
+    let mut status_message = String::new();
+    let mut preset: Option<Preset> = None;
+    let mut is_fullscreen = false;
+    let mut show_field_lines = false;
+    let mut auto_levels = AutoLevels::default();
+    let mut color_scale = ColorScale::default();
+    let mut field_overlay = FieldOverlay::default();
+    let mut auto_quality = AutoQuality::default();
+    // Seconds spent updating the grids and their heatmaps in the last frame
+    let mut grid_time = 0.0;
+
+    let mut cursor_is_over_a_charge: bool;
+    // Id of the dragged charge and where the drag started
+    let mut dragging_charge: Option<(usize, Vec2)> = None;
+    let mut history = History::new();
+    let mut inspected_charge: Option<usize> = None;
+    // Screen position where the right button went down, and whether it has been dragged since
+    let mut right_press: Option<(Vec2, bool)> = None;
+    let mut previous_mouse_screen_position = Vec2::from(mouse_position());
 
 
     loop {
         clear_background(BLACK);
         let delta_time = get_frame_time();
 
-        if is_key_pressed(KeyCode::C) {
+        // Also covers the page resizing the canvas on the web
+        #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+        let (grid_width, grid_height) = (screen_width().round().max(1.0) as u16, screen_height().round().max(1.0) as u16);
+        if (grid_width, grid_height) != (simulation.width(), simulation.height()) {
+            camera.viewport = Vec2::new(f32::from(grid_width), f32::from(grid_height));
+            simulation.resize(grid_width, grid_height);
+        }
+        if auto_quality.update(delta_time, grid_time) {
+            status_message = format!("Quality: {auto_quality}");
+        }
+        simulation.set_resolution(auto_quality.quality.resolution());
+        if simulation.grid_size() != (potential_image.width(), potential_image.height()) {
+            (potential_image, potential_texture) = new_heatmap(simulation.grid_size());
+            (field_image, field_texture) = new_heatmap(simulation.grid_size());
+        }
+
+        let is_control_down = is_key_down(KeyCode::LeftControl) || is_key_down(KeyCode::RightControl);
+        let is_shift_down = is_key_down(KeyCode::LeftShift) || is_key_down(KeyCode::RightShift);
+        if is_control_down && is_key_pressed(KeyCode::Z) && !is_shift_down {
+            history.undo(&mut simulation, &mut voltmeter.equipotentials);
+            dragging_charge = None;
+        }
+        if is_control_down && ((is_key_pressed(KeyCode::Z) && is_shift_down) || is_key_pressed(KeyCode::Y)) {
+            history.redo(&mut simulation, &mut voltmeter.equipotentials);
+            dragging_charge = None;
+        }
+        if is_control_down && is_key_pressed(KeyCode::S) {
+            status_message = match Scene::capture(&simulation, &voltmeter.equipotentials).save(Scene::DEFAULT_FILE_NAME) {
+                Ok(()) => format!("Saved scene to {}", Scene::DEFAULT_FILE_NAME),
+                Err(error) => format!("Could not save scene: {error}"),
+            };
+        }
+        if is_control_down && is_key_pressed(KeyCode::O) {
+            status_message = match Scene::load(Scene::DEFAULT_FILE_NAME) {
+                Ok(scene) => {
+                    scene.apply(&mut simulation);
+                    voltmeter.equipotentials.clone_from(&scene.equipotentials);
+                    dragging_charge = None;
+                    history.clear();
+                    format!("Loaded scene from {}", Scene::DEFAULT_FILE_NAME)
+                }
+                Err(error) => format!("Could not load scene: {error}"),
+            };
+        }
+        if is_key_pressed(KeyCode::P) {
+            let next_preset = preset.map_or(Preset::ALL[0], Preset::next);
+            next_preset.populate(&mut simulation);
+            voltmeter.clear_equipotentials();
+            dragging_charge = None;
+            history.clear();
+            preset = Some(next_preset);
+            status_message = format!("Preset: {next_preset}");
+        }
+        if is_key_pressed(KeyCode::C) && !voltmeter.equipotentials.is_empty() {
+            history.record(Edit::ClearEquipotentials(voltmeter.equipotentials.clone()));
             voltmeter.clear_equipotentials();
-            equipotential_lines_image = transparent_equipotential_lines.clone();
         }
         if is_key_pressed(KeyCode::V) {
             voltmeter.is_active = !voltmeter.is_active;
         }
+        if is_key_pressed(KeyCode::Tab) {
+            species_registry.select_next();
+        }
+        edit_custom_species(&mut species_registry);
+        if is_key_pressed(KeyCode::U) {
+            simulation.units = simulation.units.toggled();
+        }
+        if is_key_pressed(KeyCode::I) {
+            simulation.integrator = simulation.integrator.next();
+        }
+        if is_key_pressed(KeyCode::K) {
+            let collisions = &mut simulation.collisions;
+            if is_shift_down {
+                collisions.like_signs = collisions.like_signs.next();
+            } else {
+                collisions.opposite_signs = collisions.opposite_signs.next();
+            }
+            status_message = format!("Collisions: {collisions}");
+        }
+        if is_key_pressed(KeyCode::J) {
+            let collisions = &mut simulation.collisions;
+            if is_shift_down {
+                collisions.more_restitution();
+            } else {
+                collisions.less_restitution();
+            }
+            status_message = format!("Collisions: {collisions}");
+        }
+        if is_key_pressed(KeyCode::Period) {
+            simulation.timestep.faster();
+        }
+        if is_key_pressed(KeyCode::Comma) {
+            simulation.timestep.slower();
+        }
+        if is_key_pressed(KeyCode::A) {
+            simulation.adaptive_step.enabled = !simulation.adaptive_step.enabled;
+        }
+        if is_key_pressed(KeyCode::L) {
+            show_field_lines = !show_field_lines;
+        }
+        if is_key_pressed(KeyCode::N) {
+            if is_shift_down {
+                auto_levels.spacing = auto_levels.spacing.next();
+            } else {
+                auto_levels.enabled = !auto_levels.enabled;
+            }
+        }
+        if is_key_pressed(KeyCode::PageUp) {
+            auto_levels.more();
+        }
+        if is_key_pressed(KeyCode::PageDown) {
+            auto_levels.fewer();
+        }
+        if is_key_pressed(KeyCode::M) {
+            color_scale.colormap = color_scale.colormap.next();
+            status_message = format!("Colormap: {}", color_scale.colormap);
+        }
+        if is_key_pressed(KeyCode::B) {
+            if is_shift_down {
+                color_scale.auto_range = !color_scale.auto_range;
+                status_message = format!("Color range: {}", if color_scale.auto_range { "automatic" } else { "full scale" });
+            } else {
+                color_scale.scaling = color_scale.scaling.next();
+                status_message = format!("Color scaling: {}", color_scale.scaling);
+            }
+        }
+        if is_key_pressed(KeyCode::O) && !is_control_down {
+            if is_shift_down {
+                field_overlay.next_component();
+            } else {
+                field_overlay.mode = field_overlay.mode.next();
+            }
+            status_message = format!("Field overlay: {} {}", field_overlay.component, field_overlay.mode);
+        }
+        if is_key_pressed(KeyCode::E) {
+            show_diagnostics = !show_diagnostics;
+        }
+        if is_key_pressed(KeyCode::R) {
+            simulation.damping = if simulation.damping > 0.0 { 0.0 } else { Simulation::DEFAULT_DAMPING };
+        }
+        if is_key_pressed(KeyCode::Q) {
+            if is_shift_down {
+                auto_quality.toggle();
+            } else {
+                auto_quality.next();
+            }
+            status_message = format!("Quality: {auto_quality}");
+        }
+        if is_key_pressed(KeyCode::F11) {
+            is_fullscreen = !is_fullscreen;
+            set_fullscreen(is_fullscreen);
+        }
         if is_key_pressed(KeyCode::Escape) {
             toggle_simulation_state(&mut simulation_state);
 
         }
 
-        let mouse_position = Vec2 { x: mouse_position().0, y: mouse_position().1 };
+        let mouse_screen_position = Vec2::from(mouse_position());
+        if is_key_pressed(KeyCode::Home) && !camera.fit(&simulation.charges) {
+            camera = Camera::new(camera.viewport);
+        }
+        move_camera(&mut camera, mouse_screen_position, inspected_charge.is_none(), delta_time);
+        if is_mouse_button_pressed(MouseButton::Right) {
+            right_press = Some((mouse_screen_position, false));
+        }
+        if is_mouse_button_down(MouseButton::Right) && let Some((start, is_panning)) = &mut right_press {
+            if *is_panning {
+                camera.pan(mouse_screen_position - previous_mouse_screen_position);
+            } else if mouse_screen_position.distance(*start) > PAN_DRAG_THRESHOLD {
+                *is_panning = true;
+                camera.pan(mouse_screen_position - *start);
+            }
+        }
+        let is_right_clicked = is_mouse_button_released(MouseButton::Right)
+            && right_press.take().is_some_and(|(_, is_panning)| !is_panning);
+        previous_mouse_screen_position = mouse_screen_position;
+        let mouse_position = camera.screen_to_world(mouse_screen_position);
+
+        let hovered_charge = simulation.charge_index_at(mouse_position).map(|i| simulation.charges[i].id);
+        if !is_control_down {
+            edit_charges(&mut simulation, &mut history, hovered_charge, &mut inspected_charge);
+        }
 
         if is_mouse_button_pressed(MouseButton::Left) {
-            for (i, charge) in charges.iter().enumerate() {
-                if charge.drawing_circle.contains(mouse_position){
-                    cursor_is_over_a_charge = true;
-                    dragging_charge = Some(i);
-                    break;
-                }
-            }
+            dragging_charge = hovered_charge
+                .and_then(|id| simulation.charge_by_id(id))
+                .map(|charge| (charge.id, charge.center));
         }
 
-        if is_mouse_button_released(MouseButton::Left) {
-            dragging_charge = None;
+        if is_mouse_button_released(MouseButton::Left)
+            && let Some((id, from)) = dragging_charge.take()
+            && let Some(charge) = simulation.charge_by_id(id)
+            && charge.center != from {
+            history.record(Edit::Move { id, from, to: charge.center });
         }
 
         cursor_is_over_a_charge = false;
-        for (i, charge) in charges.iter_mut().enumerate() {
+        let dragging_charge_id = dragging_charge.map(|(id, _)| id);
+        for charge in &mut simulation.charges {
             // Handle visual hover state
             if charge.drawing_circle.contains(mouse_position) {
                 cursor_is_over_a_charge = true;
                 charge.is_selected = true;
             } else {
                 // Keep selection only if this is the charge being dragged
-                charge.is_selected = dragging_charge == Some(i);
+                charge.is_selected = dragging_charge_id == Some(charge.id);
             }
 
             // Update position for dragged charge
-            if is_mouse_button_down(MouseButton::Left) && dragging_charge == Some(i) {
-                charge.center = mouse_position;
-                charge.drawing_circle.center = mouse_position;
+            if is_mouse_button_down(MouseButton::Left) && dragging_charge_id == Some(charge.id) {
+                charge.set_center(mouse_position);
+                charge.velocity = Vec2::ZERO;
             }
         }
-        if (cursor_is_over_a_charge) {
+        if cursor_is_over_a_charge {
             miniquad::window::set_mouse_cursor(CursorIcon::Pointer);
         } else {
             miniquad::window::set_mouse_cursor(CursorIcon::Default);
         }
 
 
-        if is_mouse_button_pressed(MouseButton::Left) || is_mouse_button_pressed(MouseButton::Right) {
-            if voltmeter.is_active && is_mouse_button_pressed(MouseButton::Left) {
+        let is_left_clicked = is_mouse_button_pressed(MouseButton::Left);
+        if is_left_clicked || is_right_clicked {
+            if voltmeter.is_active && is_left_clicked {
                 voltmeter.add_equipotential();
             }
-            let mut mouse_pointer_is_over_charge = false;
-
-            for charge in &charges {
-                if charge.enclosing_square().contains(mouse_position) {
-                    mouse_pointer_is_over_charge = true;
-                }
-            }
+            let mouse_pointer_is_over_charge = simulation.charges.iter()
+                .any(|charge| charge.enclosing_square().contains(mouse_position));
             if !mouse_pointer_is_over_charge && !voltmeter.is_active {
-
-                spawn_charge(&mut charges, mouse_position);
+                let id = spawn_charge(&mut simulation, &species_registry, mouse_position, is_right_clicked);
+                if let Some(charge) = simulation.charge_by_id(id) {
+                    history.record(Edit::Spawn(charge.clone()));
+                }
             }
         }
         if simulation_state == Running {
 
-            update_charges(&mut charges, delta_time);
+            simulation.advance(delta_time);
 
         }
 
-        clear_potential(&mut potentials_array);
-        update_field(&mut test_charges, &charges);
-        max_potential = update_potential_and_return_max(&mut potentials_array, &charges);
-        voltmeter.update(mouse_position, &charges);
-        equipotential_lines_image = transparent_equipotential_lines.clone();
-        update_potential_images(&potentials_array, max_potential, &voltmeter.equipotentials, &mut potential_image, &mut equipotential_lines_image);
-        draw_potential(&potential_image);
-        draw_field(&test_charges);
-        draw_equipotential_lines(&equipotential_lines_image);
-        draw_charges(&charges);
+        let grid_start = get_time();
+        simulation.set_view(camera.visible_region());
+        simulation.update_field();
+        // Both grids, the field lines and the equipotentials only change along with the charges, the view or the units
+        if simulation.update_potential() {
+            potential_heatmap_scale = None;
+            field_heatmap_overlay = None;
+            field_lines = None;
+            auto_equipotentials = None;
+            voltmeter_equipotentials = None;
+        }
+        voltmeter.update(mouse_screen_position, mouse_position, &simulation.charges, &simulation.units);
+        if field_overlay.mode != OverlayMode::Instead {
+            if potential_heatmap_scale != Some(color_scale) {
+                color_scale.update_range(simulation.potentials.iter().map(|(_point, potential)| *potential), simulation.units.full_scale_potential());
+                update_potential_image(&simulation.potentials, &color_scale, &mut potential_image);
+                potential_texture.update(&potential_image);
+                potential_heatmap_scale = Some(color_scale);
+            }
+            draw_heatmap(&potential_texture, WHITE);
+        }
+        if field_overlay.mode != OverlayMode::Off {
+            if field_heatmap_overlay != Some(field_overlay) {
+                simulation.update_field_grid();
+                field_overlay.update_range(&simulation.fields);
+                update_field_image(&simulation.fields, &field_overlay, &mut field_image);
+                field_texture.update(&field_image);
+                field_heatmap_overlay = Some(field_overlay);
+            }
+            let alpha = if field_overlay.mode == OverlayMode::Alongside { FIELD_OVERLAY_ALPHA } else { 1.0 };
+            draw_heatmap(&field_texture, Color::new(1.0, 1.0, 1.0, alpha));
+        }
+        #[allow(clippy::cast_possible_truncation)]
+        {
+            grid_time = (get_time() - grid_start) as f32;
+        }
+        draw_field(&simulation.test_charges, &camera);
+        if show_field_lines {
+            let field_lines = field_lines.get_or_insert_with(|| {
+                // Traced past the view, so that lines leaving it can come back in
+                let view = simulation.view();
+                let bounds = Rect::new(view.x - view.w / 2.0, view.y - view.h / 2.0, 2.0 * view.w, 2.0 * view.h);
+                let settings = FieldLineSettings { step: FieldLineSettings::DEFAULT_STEP / camera.zoom, ..Default::default() };
+                trace_field_lines(&simulation.charges, &simulation.units, bounds, &settings)
+            });
+            draw_field_lines(field_lines, &camera);
+        }
+        if auto_levels.enabled {
+            let levels = auto_levels.levels(&simulation.potentials);
+            draw_equipotentials(cached_equipotentials(&mut auto_equipotentials, &simulation.potentials, levels, &camera), &camera, AUTO_EQUIPOTENTIAL_COLOR);
+        }
+        draw_equipotentials(cached_equipotentials(&mut voltmeter_equipotentials, &simulation.potentials, voltmeter.equipotentials.clone(), &camera), &camera, GREEN);
+        set_camera(&camera.to_camera_2d());
+        draw_charges(&simulation.charges);
+        set_default_camera();
 
         voltmeter.draw();
+        if let Some(charge) = inspected_charge.and_then(|id| simulation.charge_by_id(id)) {
+            draw_inspector(charge, &camera);
+        }
+        let mut colorbar_right_offset = COLORBAR_RIGHT_OFFSET;
+        if field_overlay.mode != OverlayMode::Off {
+            draw_colorbar(&field_overlay.scale, Rect::new(screen_width() + colorbar_right_offset, COLORBAR_TOP, COLORBAR_SIZE.x, COLORBAR_SIZE.y), simulation.units.field_unit());
+            colorbar_right_offset += COLORBAR_SPACING;
+        }
+        if field_overlay.mode != OverlayMode::Instead {
+            draw_colorbar(&color_scale, Rect::new(screen_width() + colorbar_right_offset, COLORBAR_TOP, COLORBAR_SIZE.x, COLORBAR_SIZE.y), "V");
+        }
+        if show_diagnostics {
+            draw_diagnostics_panel(&simulation.diagnostics, DIAGNOSTICS_PANEL_AREA);
+        }
         draw_fps();
+        draw_hud(&[
+            format!("Species: {}", species_registry.selected()),
+            format!("Units: {}", simulation.units),
+            format!("Integrator: {}, damping: {} 1/s", simulation.integrator, simulation.damping),
+            format!("Collisions: {}", simulation.collisions),
+            format!("Speed: x{}{}, step: {:.2} ms x {} sub-steps", simulation.timestep.speed, reached_speed_note(&simulation.timestep), simulation.timestep.step * 1000.0, simulation.timestep.substeps),
+            format!("Adaptive step: {}, last step: {:.3} ms", if simulation.adaptive_step.enabled { "on" } else { "off" }, simulation.last_step() * 1000.0),
+            format!("Zoom: x{:.2}", camera.zoom),
+            format!("Quality: {auto_quality}"),
+            if auto_levels.enabled { format!("Auto equipotentials: {} {}", auto_levels.count, auto_levels.spacing) } else { String::new() },
+            status_message.clone(),
+        ]);
         draw_simulation_state(&simulation_state);
         next_frame().await;
     }
@@ -192,134 +472,22 @@ fn toggle_simulation_state(simulation_state: &mut SimulationState) {
     }
 }
 
-fn update_field(test_charges: &mut Vec<TestCharge>, charges: &Vec<PointCharge>) {
-    for test_charge in &mut *test_charges {
-        test_charge.clear_forces();
-    }
-    for test_charge in &mut *test_charges {
-        for charge in charges {
-            test_charge.force_with(charge);
-        }
-    }
-
-    for test_charge in &mut *test_charges {
-
-        for charge in charges {
-           if test_charge.center.distance_squared(charge.center) < (1.5*(PointCharge::DEFAULT_RADIUS)).powi(2) {
-               test_charge.is_hidden = true;
-               break;
-           }
-            test_charge.is_hidden = false;
-        }
-        if (!test_charge.is_hidden) {
-            test_charge.calculate_net_force();
-        }
-    }
-
-
-    let max_magnitude: f32 =  test_charges.iter()
-        .map(|charge| charge.net_force.x)
-        .max_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal))
-        .unwrap_or(f32::INFINITY);
+/// Draws the field arrows in screen space, so they keep their size at any zoom.
+fn draw_field(test_charges: &[TestCharge], camera: &Camera) {
     for test_charge in test_charges {
-        test_charge.set_max_force(max_magnitude);
-        test_charge.update_arrow();
-    }
-
-
-    }
-
-
-fn update_charges(charges: &mut Vec<PointCharge>, delta: f32) {
-    // Clear forces
-    for charge in charges.iter_mut() {
-        charge.clear_forces();
-        charge.is_colliding = false;
-    }
-
-    // Track merges
-    let mut to_remove = vec![false; charges.len()];
-    let mut new_charges = Vec::new();
-
-    // Handle collisions and forces
-    for i in 0..charges.len() {
-        if to_remove[i] { continue; }
-
-        for j in i+1..charges.len() {
-            if to_remove[j] { continue; }
-
-            let (first, second) = charges.split_at_mut(j);
-            let charge1 = &mut first[i];
-            let charge2 = &mut second[0];
-
-            charge1.force_with(charge2);
-            charge1.check_collision_with(charge2);
-
-            // Check for merge condition
-            if charge1.is_colliding && charge1.should_merge_with(charge2) {
-                to_remove[i] = true;
-                to_remove[j] = true;
-
-                // Create new neutral charge at midpoint
-                let new_center = (charge1.center + charge2.center) * 0.5;
-
-                // Conserve momentum in velocity
-                let total_mass = charge1.m + charge2.m;
-                let new_velocity = if total_mass > 0.0 {
-                    (charge1.velocity * charge1.m + charge2.velocity * charge2.m) / total_mass
-                } else {
-                    Vec2::ZERO
-                };
-
-                let mut neutral = PointCharge::new_neutral_charge_from_merge(
-                    charge1.id, // Reuse an ID
-                    new_center,
-                    charge1.is_fixed && charge2.is_fixed
-                );
-
-                neutral.velocity = new_velocity;
-                new_charges.push(neutral);
-                break;
-            }
-        }
-        // Reverse interactions (i with j<i) - this ensures all charges get updated
-        for j in 0..i {
-            if to_remove[j] { continue; }
-
-            let (first, second) = charges.split_at_mut(i);
-            let charge2 = &mut first[j];
-            let charge1 = &mut second[0];
-
-            charge1.force_with(charge2);
-            // No collision check needed here as it's already done in the forward pass
-        }
-    }
-
-    // Remove merged charges (in reverse order)
-    for i in (0..charges.len()).rev() {
-        if to_remove[i] {
-            charges.swap_remove(i);
-        }
-    }
-
-    // Add new neutral charges
-    charges.extend(new_charges);
-
-    // Update physics
-    for charge in charges.iter_mut() {
-        charge.calculate_net_force();
-        charge.calculate_max_force();
-        charge.calculate_acceleration();
-        charge.calculate_velocity();
-        charge.movement(delta);
+        test_charge.draw_at(camera.world_to_screen(test_charge.center));
     }
 }
-fn draw_field(test_charges: &Vec<TestCharge>) {
-    for test_charge in test_charges {
-        test_charge.draw();
+fn draw_field_lines(field_lines: &[FieldLine], camera: &Camera) {
+    for field_line in field_lines {
+        for segment in field_line.points.windows(2) {
+            let (start, end) = (camera.world_to_screen(segment[0]), camera.world_to_screen(segment[1]));
+            draw_line(start.x, start.y, end.x, end.y, 1.5, FIELD_LINE_COLOR);
+        }
     }
 }
-fn draw_charges(charges: &Vec<PointCharge>) {
+
+fn draw_charges(charges: &[PointCharge]) {
     /*for charge in charges {
         charge.draw_forces();
     }*/
@@ -333,96 +501,218 @@ fn draw_charges(charges: &Vec<PointCharge>) {
     }
 }
 
-fn spawn_charge(charges: &mut Vec<PointCharge>, mouse_position: Vec2) {
+/// Spawns the selected species, or its antiparticle, returning the new charge's id.
+fn spawn_charge(simulation: &mut Simulation, species_registry: &SpeciesRegistry, mouse_position: Vec2, is_antiparticle: bool) -> usize {
+    let is_fixed = is_key_down(KeyCode::LeftShift);
+    let species = species_registry.selected();
+    if is_antiparticle {
+        simulation.spawn_species(&species.antiparticle(), mouse_position, is_fixed)
+    } else {
+        simulation.spawn_species(species, mouse_position, is_fixed)
+    }
+}
 
-    let id = charges.len() + 1;
-    if is_mouse_button_pressed(MouseButton::Left) {
-        if is_key_down(KeyCode::LeftShift) {
-            charges.push(PointCharge::new_positive_charge(id, mouse_position, true));
-        } else {
-            charges.push(PointCharge::new_positive_charge(id, mouse_position, false));
-        }
-    } else if is_mouse_button_pressed(MouseButton::Right) {
-        if is_key_down(KeyCode::LeftShift) {
-            charges.push(PointCharge::new_negative_charge(id, mouse_position, true));
-        } else {
-            charges.push(PointCharge::new_negative_charge(id, mouse_position, false));
-        }
+/// The speed actually reached, when the frame could not run every step due.
+fn reached_speed_note(timestep: &FixedTimestep) -> String {
+    if timestep.reached_speed() < 0.99 * timestep.speed {
+        format!(" (reached x{:.1})", timestep.reached_speed())
+    } else {
+        String::new()
     }
 }
 
+/// Zooms around the cursor with the mouse wheel and pans with the arrow keys,
+/// unless they are taken by the inspector.
+fn move_camera(camera: &mut Camera, mouse_screen_position: Vec2, can_use_arrows: bool, delta_time: f32) {
+    let (_, wheel) = mouse_wheel();
+    if wheel != 0.0 {
+        camera.zoom_at(mouse_screen_position, ZOOM_STEP.powf(wheel.signum()));
+    }
+    if !can_use_arrows {
+        return;
+    }
+    let mut direction = Vec2::ZERO;
+    for (key, key_direction) in [
+        (KeyCode::Left, Vec2::NEG_X),
+        (KeyCode::Right, Vec2::X),
+        (KeyCode::Up, Vec2::NEG_Y),
+        (KeyCode::Down, Vec2::Y),
+    ] {
+        if is_key_down(key) {
+            direction += key_direction;
+        }
+    }
+    camera.pan(-direction * PAN_SPEED * delta_time);
+}
 
+/// Applies the charge editing shortcuts to the inspected charge, or to the
+/// hovered one when the inspector is closed, recording every edit in `history`.
+fn edit_charges(simulation: &mut Simulation, history: &mut History, hovered_charge: Option<usize>, inspected_charge: &mut Option<usize>) {
+    if inspected_charge.is_some_and(|id| simulation.charge_by_id(id).is_none()) {
+        // Merged or deleted since it was opened
+        *inspected_charge = None;
+    }
+    if is_key_pressed(KeyCode::Enter) {
+        *inspected_charge = if *inspected_charge == hovered_charge { None } else { hovered_charge };
+    }
 
+    if is_mouse_button_pressed(MouseButton::Middle) && let Some(id) = hovered_charge {
+        if let Some(removed) = simulation.remove_charge(id) {
+            history.record(Edit::Delete(removed));
+        }
+        return;
+    }
+    let Some(id) = inspected_charge.or(hovered_charge) else {
+        return;
+    };
+    if is_key_pressed(KeyCode::Delete) || is_key_pressed(KeyCode::Backspace) {
+        if let Some(removed) = simulation.remove_charge(id) {
+            history.record(Edit::Delete(removed));
+        }
+        return;
+    }
+    if is_key_pressed(KeyCode::D)
+        && let Some(copy_id) = simulation.duplicate_charge(id)
+        && let Some(copy) = simulation.charge_by_id(copy_id) {
+        history.record(Edit::Spawn(copy.clone()));
+    }
 
-fn clear_potential(potentials_array: &mut ArrayBase<OwnedRepr<(Vec2, f32)>, Ix2>) {
-    potentials_array.par_map_inplace(
-        |(_point, potential)| *potential = 0.0f32
-    );
+    let Some(charge) = simulation.charge_by_id_mut(id) else {
+        return;
+    };
+    let before = ChargeProperties::capture(charge);
+    if is_key_pressed(KeyCode::F) {
+        charge.set_fixed(!charge.is_fixed);
+    }
+    if is_key_pressed(KeyCode::S) {
+        charge.flip_sign();
+    }
+    if *inspected_charge == Some(id) {
+        if is_key_pressed(KeyCode::Up) {
+            charge.set_charge(charge.q() + 0.5 * PointCharge::DEFAULT_CHARGE);
+        }
+        if is_key_pressed(KeyCode::Down) {
+            charge.set_charge(charge.q() - 0.5 * PointCharge::DEFAULT_CHARGE);
+        }
+        if is_key_pressed(KeyCode::Right) {
+            charge.m *= 2.0;
+        }
+        if is_key_pressed(KeyCode::Left) {
+            charge.m /= 2.0;
+        }
+    }
+    let after = ChargeProperties::capture(charge);
+    if after != before {
+        history.record(Edit::Change { id, before, after });
+    }
 }
 
+fn edit_custom_species(species_registry: &mut SpeciesRegistry) {
+    if species_registry.selected().name != "Custom" {
+        return;
+    }
+    let species = species_registry.selected_mut();
+    if is_key_pressed(KeyCode::Equal) {
+        species.charge += 0.5 * PointCharge::DEFAULT_CHARGE;
+    }
+    if is_key_pressed(KeyCode::Minus) {
+        species.charge -= 0.5 * PointCharge::DEFAULT_CHARGE;
+    }
+    if is_key_pressed(KeyCode::RightBracket) {
+        species.mass *= 2.0;
+    }
+    if is_key_pressed(KeyCode::LeftBracket) {
+        species.mass /= 2.0;
+    }
+}
 
-
-fn update_potential_and_return_max(potentials_array: &mut ArrayBase<OwnedRepr<(Vec2, f32)>, Ix2>, charges: &Vec<PointCharge>) -> f32 {
-    // Process calculations in parallel and modify values in place
-    for charge in charges {
-        if charge.sign == Neutral { continue}
-        potentials_array.par_map_inplace(
-            |(point, potential)| *potential += charge.potential_contribution_at(point)
-        );
-
+/// Draws one line of text per entry, stacked upwards from the bottom-left corner.
+fn draw_hud(lines: &[String]) {
+    for (i, line) in lines.iter().rev().enumerate() {
+        let y = screen_height() + HUD_BOTTOM_LEFT_OFFSET.y - i as f32 * HUD_LINE_HEIGHT;
+        draw_text(line, HUD_BOTTOM_LEFT_OFFSET.x, y, HUD_FONT_SIZE, WHITE);
     }
-    // Return fixed max value as you're doing
-    100.0
 }
 
-fn update_potential_images(potentials_array: &ArrayBase<OwnedRepr<(Vec2, f32)>, Ix2>, max_potential: f32, equipotentials: &[f32], potential_image: &mut Image, equipotential_lines_image: &mut Image) {
+fn update_potential_image(potentials_array: &Array2<(Vec2, f32)>, color_scale: &ColorScale, potential_image: &mut Image) {
     // Process all points in parallel and collect updates
-    let updates: Vec<(Vec2, Color, bool)> = potentials_array.par_iter()
-        .map(|(point, potential)| {
-            // Check for equipotential lines first
-            let is_equipotential = if (potential.abs() < 10.0) { equipotentials.iter().any(|equip| is_close!(*potential+10.0, *equip+10.0, abs_tol=1e-1)) } else { equipotentials.iter().any(|equip| is_close!(*potential, *equip, rel_tol=1e-2, method=AVERAGE)) };
-
-            // Determine color based on potential or equipotential status
-            let color = if is_equipotential {
-                GREEN
-            } else {
-                color_based_on_potential(*potential, max_potential)
-            };
-
-            (*point, color, is_equipotential)
-        }).collect();
-    // equipotential_lines_image.update(TRANSPARENT_COLOR_SLICE);
-    // Apply updates to both images sequentially
-    for (point, color, is_equipotential) in updates {
+    let updates: Vec<((usize, usize), Color)> = Zip::indexed(potentials_array).into_par_iter()
+        .map(|(pixel, (_point, potential))| (pixel, color_scale.color(*potential)))
+        .collect();
+    for ((x, y), color) in updates {
         #[allow(clippy::cast_possible_truncation)]
-        potential_image.set_pixel(point.x as u32, point.y as u32, color);
-
-        if is_equipotential {
-            equipotential_lines_image.set_pixel(point.x as u32, point.y as u32, GREEN);
-        }
+        potential_image.set_pixel(x as u32, y as u32, color);
+    }
+}
+fn update_field_image(fields: &Array2<Vec2>, field_overlay: &FieldOverlay, field_image: &mut Image) {
+    let updates: Vec<((usize, usize), Color)> = Zip::indexed(fields).into_par_iter()
+        .map(|(pixel, field)| (pixel, field_overlay.color(*field)))
+        .collect();
+    for ((x, y), color) in updates {
+        #[allow(clippy::cast_possible_truncation)]
+        field_image.set_pixel(x as u32, y as u32, color);
     }
 }
-fn draw_potential(potential_image: &Image) {
 
+/// A black image of `size` pixels and a texture to upload it to, filtered
+/// bilinearly when stretched over the window.
+fn new_heatmap((width, height): (usize, usize)) -> (Image, Texture2D) {
+    #[allow(clippy::cast_possible_truncation)]
+    let image = Image::gen_image_color(width as u16, height as u16, BLACK);
+    let texture = Texture2D::from_image(&image);
+    texture.set_filter(FilterMode::Linear);
+    (image, texture)
+}
 
+/// Draws a heatmap stretched over the whole window, multiplied by `tint`.
+fn draw_heatmap(texture: &Texture2D, tint: Color) {
+    draw_texture_ex(texture, 0.0, 0.0, tint, DrawTextureParams {
+        dest_size: Some(Vec2::new(screen_width(), screen_height())),
+        ..Default::default()
+    });
+}
 
-     // Create a texture from the image and draw it
-     let texture = Texture2D::from_image(potential_image);
-     draw_texture(&texture, 0.0, 0.0, WHITE);
+fn trace_equipotentials(potentials_array: &Array2<(Vec2, f32)>, levels: &[f32], camera: &Camera) -> Vec<Contour> {
+    levels.par_iter()
+        .flat_map_iter(|level| extract_contours(potentials_array, *level))
+        .map(|contour| contour.smoothed(CONTOUR_KNOT_SPACING / camera.zoom))
+        .collect()
 }
 
-fn draw_equipotential_lines(equipotential_lines_image: &Image) {
-    // Create a texture from the image and draw it
-    let texture = Texture2D::from_image(equipotential_lines_image);
-    draw_texture(&texture, 0.0, 0.0, WHITE);
+/// The contours at `levels`, traced again only when they differ from the cached ones.
+fn cached_equipotentials<'a>(cache: &'a mut Option<(Vec<f32>, Vec<Contour>)>, potentials_array: &Array2<(Vec2, f32)>, levels: Vec<f32>, camera: &Camera) -> &'a [Contour] {
+    if cache.as_ref().is_none_or(|(cached_levels, _)| *cached_levels != levels) {
+        let contours = trace_equipotentials(potentials_array, &levels, camera);
+        *cache = Some((levels, contours));
+    }
+    cache.as_ref().map_or(&[], |(_, contours)| contours)
+}
+
+/// Draws each contour with its voltage written halfway along it.
+fn draw_equipotentials(contours: &[Contour], camera: &Camera, color: Color) {
+    for contour in contours {
+        let points: Vec<Vec2> = contour.points.iter().map(|point| camera.world_to_screen(*point)).collect();
+        for segment in points.windows(2) {
+            draw_line(segment[0].x, segment[0].y, segment[1].x, segment[1].y, 2.0, color);
+        }
+        if let Some(middle) = points.get(points.len() / 2) {
+            let label = format!("{:.1} V", contour.level);
+            let size = measure_text(&label, None, CONTOUR_LABEL_FONT_SIZE, 1.0);
+            let corner = *middle + Vec2::new(4.0, -4.0 - size.height);
+            draw_rectangle(corner.x - 2.0, corner.y - 2.0, size.width + 4.0, size.height + 4.0, color_u8!(0, 0, 0, 160));
+            draw_text(&label, corner.x, corner.y + size.offset_y, f32::from(CONTOUR_LABEL_FONT_SIZE), color);
+        }
+    }
 }
 
 fn draw_simulation_state(simulation_state: &SimulationState) {
     if simulation_state == &Running {
-        draw_triangle(RUNNING_SIMULATION_TRIANGLE_VERTICES.0 ,RUNNING_SIMULATION_TRIANGLE_VERTICES.1, RUNNING_SIMULATION_TRIANGLE_VERTICES.2, WHITE);
+        let top_right = Vec2::new(screen_width(), 0.0);
+        draw_triangle(top_right + RUNNING_SIMULATION_TRIANGLE_VERTICES.0, top_right + RUNNING_SIMULATION_TRIANGLE_VERTICES.1, top_right + RUNNING_SIMULATION_TRIANGLE_VERTICES.2, WHITE);
 
     } else {
-        draw_rectangle(PAUSED_SIMULATION_RECTANGLES.0.x, PAUSED_SIMULATION_RECTANGLES.0.y, PAUSED_SIMULATION_RECTANGLES.0.w, PAUSED_SIMULATION_RECTANGLES.0.h, WHITE);
-        draw_rectangle(PAUSED_SIMULATION_RECTANGLES.1.x, PAUSED_SIMULATION_RECTANGLES.1.y, PAUSED_SIMULATION_RECTANGLES.1.w, PAUSED_SIMULATION_RECTANGLES.1.h, WHITE);
+        let right = screen_width();
+        draw_rectangle(right + PAUSED_SIMULATION_RECTANGLES.0.x, PAUSED_SIMULATION_RECTANGLES.0.y, PAUSED_SIMULATION_RECTANGLES.0.w, PAUSED_SIMULATION_RECTANGLES.0.h, WHITE);
+        draw_rectangle(right + PAUSED_SIMULATION_RECTANGLES.1.x, PAUSED_SIMULATION_RECTANGLES.1.y, PAUSED_SIMULATION_RECTANGLES.1.w, PAUSED_SIMULATION_RECTANGLES.1.h, WHITE);
     }
 }
@@ -0,0 +1,167 @@
+use crate::colormap::{ColorScale, Colormap, Scaling};
+use crate::Cycle;
+use macroquad::color::Color;
+use macroquad::math::Vec2;
+use ndarray::Array2;
+use std::fmt;
+
+/// Quantity of the electric field shown by the overlay.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum FieldComponent {
+    #[default]
+    Magnitude,
+    X,
+    Y,
+}
+
+impl FieldComponent {
+    #[must_use]
+    pub fn name(self) -> &'static str {
+        match self {
+            FieldComponent::Magnitude => "|E|",
+            FieldComponent::X => "Ex",
+            FieldComponent::Y => "Ey",
+        }
+    }
+
+    #[must_use]
+    pub fn value(self, field: Vec2) -> f32 {
+        match self {
+            FieldComponent::Magnitude => field.length(),
+            FieldComponent::X => field.x,
+            FieldComponent::Y => field.y,
+        }
+    }
+
+    /// Log scaling on a sequential map for the magnitude, symmetric log scaling
+    /// on a diverging map for the signed components.
+    #[must_use]
+    pub fn default_scale(self) -> ColorScale {
+        match self {
+            FieldComponent::Magnitude => ColorScale { colormap: Colormap::Inferno, scaling: Scaling::Log, ..Default::default() },
+            FieldComponent::X | FieldComponent::Y => ColorScale { colormap: Colormap::CoolWarm, scaling: Scaling::Symlog, ..Default::default() },
+        }
+    }
+}
+
+impl Cycle for FieldComponent {
+    const ALL: &'static [Self] = &[FieldComponent::Magnitude, FieldComponent::X, FieldComponent::Y];
+}
+
+impl fmt::Display for FieldComponent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+/// How the field heatmap is combined with the potential heatmap.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum OverlayMode {
+    #[default]
+    Off,
+    /// Blended over the potential heatmap.
+    Alongside,
+    /// Drawn in place of the potential heatmap.
+    Instead,
+}
+
+impl Cycle for OverlayMode {
+    const ALL: &'static [Self] = &[OverlayMode::Off, OverlayMode::Alongside, OverlayMode::Instead];
+}
+
+impl fmt::Display for OverlayMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OverlayMode::Off => write!(f, "off"),
+            OverlayMode::Alongside => write!(f, "over the potential"),
+            OverlayMode::Instead => write!(f, "instead of the potential"),
+        }
+    }
+}
+
+/// Heatmap of one component of the electric field sampled over the view.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct FieldOverlay {
+    pub mode: OverlayMode,
+    pub component: FieldComponent,
+    pub scale: ColorScale,
+}
+
+impl Default for FieldOverlay {
+    fn default() -> Self {
+        let component = FieldComponent::default();
+        FieldOverlay { mode: OverlayMode::default(), component, scale: component.default_scale() }
+    }
+}
+
+impl FieldOverlay {
+    /// Switches to the next component, with the color scale suited to it.
+    pub fn next_component(&mut self) {
+        self.component = self.component.next();
+        self.scale = self.component.default_scale();
+    }
+
+    /// Fits the color range to the shown component of `fields`.
+    pub fn update_range(&mut self, fields: &Array2<Vec2>) {
+        let component = self.component;
+        self.scale.update_range(fields.iter().map(|field| component.value(*field)), 1.0);
+    }
+
+    #[must_use]
+    pub fn color(&self, field: Vec2) -> Color {
+        self.scale.color(self.component.value(field))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn components_are_read_from_the_field() {
+        let field = Vec2::new(3.0, -4.0);
+        assert_eq!(FieldComponent::Magnitude.value(field), 5.0);
+        assert_eq!(FieldComponent::X.value(field), 3.0);
+        assert_eq!(FieldComponent::Y.value(field), -4.0);
+    }
+
+    #[test]
+    fn switching_components_resets_the_scale_to_suit_them() {
+        let mut overlay = FieldOverlay::default();
+        overlay.scale.range = 42.0;
+        overlay.next_component();
+        assert_eq!(overlay.component, FieldComponent::X);
+        assert_eq!(overlay.scale, FieldComponent::X.default_scale());
+        assert!(!overlay.scale.colormap.is_sequential());
+        overlay.next_component();
+        overlay.next_component();
+        assert_eq!(overlay.component, FieldComponent::Magnitude);
+        assert!(overlay.scale.colormap.is_sequential());
+    }
+
+    #[test]
+    fn range_follows_the_shown_component() {
+        // 100 samples along x, growing from 1 to 100
+        let fields = Array2::from_shape_fn((10, 10), |(x, y)| Vec2::new((10 * y + x + 1) as f32, 0.0));
+        let mut overlay = FieldOverlay::default();
+        overlay.update_range(&fields);
+        assert!((overlay.scale.range - 95.0).abs() <= 1.0, "{}", overlay.scale.range);
+
+        overlay.next_component();
+        overlay.next_component();
+        overlay.update_range(&fields);
+        // Ey is zero everywhere, which falls back to the unit range
+        assert_eq!(overlay.scale.range, 1.0);
+    }
+
+    #[test]
+    fn signed_components_color_opposite_fields_differently() {
+        let mut overlay = FieldOverlay::default();
+        overlay.next_component();
+        overlay.scale.range = 10.0;
+        assert_ne!(overlay.color(Vec2::new(5.0, 0.0)), overlay.color(Vec2::new(-5.0, 0.0)));
+        // The magnitude ignores the direction
+        let overlay = FieldOverlay::default();
+        assert_eq!(overlay.color(Vec2::new(5.0, 0.0)), overlay.color(Vec2::new(0.0, -5.0)));
+    }
+}
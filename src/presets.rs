@@ -0,0 +1,199 @@
+use crate::charges::PointCharge;
+use crate::simulation::Simulation;
+use crate::species::Species;
+use crate::Cycle;
+use macroquad::math::Vec2;
+use std::f32::consts::TAU;
+use std::fmt;
+
+/// Ready-made configurations, laid out around the center of the current view.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Preset {
+    Dipole,
+    Quadrupole,
+    ParallelPlateCapacitor,
+    Ring,
+    RutherfordScattering,
+    /// In-plane electrostatic confinement by a ring of like charges, standing in for
+    /// the electric part of a Penning trap (there is no magnetic field to add).
+    PenningTrap,
+}
+
+impl Preset {
+    #[must_use]
+    pub fn name(self) -> &'static str {
+        match self {
+            Preset::Dipole => "Dipole",
+            Preset::Quadrupole => "Quadrupole",
+            Preset::ParallelPlateCapacitor => "Parallel-plate capacitor",
+            Preset::Ring => "Ring of charges",
+            Preset::RutherfordScattering => "Rutherford scattering",
+            Preset::PenningTrap => "Penning-like trap",
+        }
+    }
+
+    #[must_use]
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.iter().copied().find(|preset| preset.name().eq_ignore_ascii_case(name))
+    }
+
+    /// Damping this configuration needs to behave as intended, `None` when any will do.
+    #[must_use]
+    pub fn damping(self) -> Option<f32> {
+        match self {
+            // Nothing moves
+            Preset::Dipole | Preset::Quadrupole | Preset::ParallelPlateCapacitor | Preset::Ring => None,
+            // Orbits and scattering angles only show without losses
+            Preset::RutherfordScattering | Preset::PenningTrap => Some(0.0),
+        }
+    }
+
+    /// Replaces the charges of `simulation` with this configuration, and its damping
+    /// with the configuration's if it needs one.
+    pub fn populate(self, simulation: &mut Simulation) {
+        simulation.clear();
+        if let Some(damping) = self.damping() {
+            simulation.damping = damping;
+        }
+        let center = simulation.view().center();
+        let unit = PointCharge::DEFAULT_CHARGE;
+
+        match self {
+            Preset::Dipole => {
+                simulation.spawn_charge(center - Vec2::new(100.0, 0.0), unit, true);
+                simulation.spawn_charge(center + Vec2::new(100.0, 0.0), -unit, true);
+            }
+            Preset::Quadrupole => {
+                for (offset, q) in [
+                    (Vec2::new(-100.0, -100.0), unit),
+                    (Vec2::new(100.0, -100.0), -unit),
+                    (Vec2::new(100.0, 100.0), unit),
+                    (Vec2::new(-100.0, 100.0), -unit),
+                ] {
+                    simulation.spawn_charge(center + offset, q, true);
+                }
+            }
+            Preset::ParallelPlateCapacitor => {
+                const PLATE_CHARGES: usize = 15;
+                const SPACING: f32 = 32.0;
+                const GAP: f32 = 200.0;
+                let half_width = SPACING * (PLATE_CHARGES - 1) as f32 / 2.0;
+                for i in 0..PLATE_CHARGES {
+                    let x = center.x - half_width + SPACING * i as f32;
+                    simulation.spawn_charge(Vec2::new(x, center.y - GAP / 2.0), unit, true);
+                    simulation.spawn_charge(Vec2::new(x, center.y + GAP / 2.0), -unit, true);
+                }
+            }
+            Preset::Ring => {
+                for position in ring(center, 150.0, 12) {
+                    simulation.spawn_charge(position, unit, true);
+                }
+            }
+            Preset::RutherfordScattering => {
+                let nucleus = Species::new("Nucleus", 10.0 * unit, 50.0 * PointCharge::DEFAULT_MASS, None);
+                simulation.spawn_species(&nucleus, center + Vec2::new(100.0, 0.0), true);
+
+                // Fast enough for a head-on alpha particle to turn back 80 px away from the nucleus
+                let alpha = Species::alpha_particle();
+                let closest_approach_energy = simulation.units.potential_energy(alpha.charge, nucleus.charge, 80.0);
+                let speed = simulation.units.metres_to_pixels((2.0 * closest_approach_energy / alpha.mass).sqrt());
+                for impact_parameter in [-160.0, -100.0, -50.0, 0.0, 50.0, 100.0, 160.0] {
+                    let id = simulation.next_id();
+                    let mut particle = alpha.spawn(id, center + Vec2::new(-360.0, impact_parameter), false);
+                    particle.velocity = Vec2::new(speed, 0.0);
+                    simulation.add_charge(particle);
+                }
+            }
+            Preset::PenningTrap => {
+                for position in ring(center, 200.0, 16) {
+                    simulation.spawn_charge(position, unit, true);
+                }
+                let proton = Species::proton();
+                for (offset, velocity) in [
+                    (Vec2::new(-40.0, 0.0), Vec2::new(0.0, 30.0)),
+                    (Vec2::new(40.0, 20.0), Vec2::new(-20.0, 0.0)),
+                    (Vec2::new(0.0, -50.0), Vec2::new(25.0, 10.0)),
+                ] {
+                    let id = simulation.next_id();
+                    let mut particle = proton.spawn(id, center + offset, false);
+                    particle.velocity = velocity;
+                    simulation.add_charge(particle);
+                }
+            }
+        }
+        simulation.update_forces();
+    }
+}
+
+impl Cycle for Preset {
+    const ALL: &'static [Self] = &[
+        Preset::Dipole,
+        Preset::Quadrupole,
+        Preset::ParallelPlateCapacitor,
+        Preset::Ring,
+        Preset::RutherfordScattering,
+        Preset::PenningTrap,
+    ];
+}
+
+impl fmt::Display for Preset {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+/// `count` points evenly spread on a circle.
+fn ring(center: Vec2, radius: f32, count: usize) -> impl Iterator<Item = Vec2> {
+    (0..count).map(move |i| center + radius * Vec2::from_angle(TAU * i as f32 / count as f32))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn populated(preset: Preset) -> Simulation {
+        let mut simulation = Simulation::new(1200, 900);
+        preset.populate(&mut simulation);
+        simulation
+    }
+
+    #[test]
+    fn presets_have_the_expected_charges() {
+        for (preset, count, fixed) in [
+            (Preset::Dipole, 2, 2),
+            (Preset::Quadrupole, 4, 4),
+            (Preset::ParallelPlateCapacitor, 30, 30),
+            (Preset::Ring, 12, 12),
+            (Preset::RutherfordScattering, 8, 1),
+            (Preset::PenningTrap, 19, 16),
+        ] {
+            let simulation = populated(preset);
+            assert_eq!(simulation.charges.len(), count, "{preset}");
+            assert_eq!(simulation.charges.iter().filter(|charge| charge.is_fixed).count(), fixed, "{preset}");
+        }
+    }
+
+    #[test]
+    fn preset_charges_do_not_overlap() {
+        for &preset in Preset::ALL {
+            let simulation = populated(preset);
+            for (i, a) in simulation.charges.iter().enumerate() {
+                for b in &simulation.charges[i + 1..] {
+                    let (a_circle, b_circle) = (&a.drawing_circle, &b.drawing_circle);
+                    // Touching, as the plates do, is not overlapping
+                    assert!(a.center.distance(b.center) >= a_circle.radius + b_circle.radius, "{preset}: charges {} and {} overlap", a.id, b.id);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn presets_keep_the_damping_unless_they_need_their_own() {
+        for &preset in Preset::ALL {
+            let mut simulation = Simulation::new(1200, 900);
+            simulation.damping = 1.25;
+            preset.populate(&mut simulation);
+            assert_eq!(simulation.damping, preset.damping().unwrap_or(1.25), "{preset}");
+        }
+    }
+}
@@ -0,0 +1,210 @@
+use crate::Cycle;
+use std::fmt;
+
+/// Resolution of the potential and field grids relative to the screen; the
+/// heatmaps are upsampled to the screen with bilinear filtering.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Quality {
+    /// One sample per screen pixel.
+    #[default]
+    Full,
+    Half,
+    Quarter,
+}
+
+impl Quality {
+    #[must_use]
+    pub fn name(self) -> &'static str {
+        match self {
+            Quality::Full => "full",
+            Quality::Half => "half",
+            Quality::Quarter => "quarter",
+        }
+    }
+
+    /// Grid samples per screen pixel, along each axis.
+    #[must_use]
+    pub fn resolution(self) -> f32 {
+        match self {
+            Quality::Full => 1.0,
+            Quality::Half => 0.5,
+            Quality::Quarter => 0.25,
+        }
+    }
+
+    /// The next lower quality, if any.
+    #[must_use]
+    pub fn lower(self) -> Option<Self> {
+        match self {
+            Quality::Full => Some(Quality::Half),
+            Quality::Half => Some(Quality::Quarter),
+            Quality::Quarter => None,
+        }
+    }
+
+    /// The next higher quality, if any.
+    #[must_use]
+    pub fn higher(self) -> Option<Self> {
+        match self {
+            Quality::Full => None,
+            Quality::Half => Some(Quality::Full),
+            Quality::Quarter => Some(Quality::Half),
+        }
+    }
+}
+
+impl Cycle for Quality {
+    const ALL: &'static [Self] = &[Quality::Full, Quality::Half, Quality::Quarter];
+}
+
+impl fmt::Display for Quality {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+/// Picks the grid quality, lowering it while updating the grids takes longer
+/// than a budget and raising it back once they are well within it.
+///
+/// The time spent on the grids is tracked rather than the frame time, which
+/// vsync rounds up to the refresh interval whatever the quality.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct AutoQuality {
+    pub enabled: bool,
+    pub quality: Quality,
+    /// Longest acceptable time spent on the grids per frame, in seconds.
+    pub grid_budget: f32,
+    /// Exponential moving average of the time spent on the grids, in seconds.
+    smoothed_grid_time: f32,
+    /// Time left, in seconds, before the quality may change again.
+    cooldown: f32,
+}
+
+impl Default for AutoQuality {
+    fn default() -> Self {
+        AutoQuality {
+            enabled: true,
+            quality: Quality::default(),
+            grid_budget: Self::DEFAULT_GRID_BUDGET,
+            smoothed_grid_time: 0.0,
+            cooldown: Self::COOLDOWN,
+        }
+    }
+}
+
+impl AutoQuality {
+    // Half a frame at 60 FPS, leaving the rest to the simulation and drawing
+    pub const DEFAULT_GRID_BUDGET: f32 = 0.008;
+    // Weight of the latest frame in the moving average
+    const SMOOTHING: f32 = 0.1;
+    // Fraction of the budget below which the quality is raised; the next quality
+    // has four times the samples, so this leaves a margin below the budget
+    const RAISE_THRESHOLD: f32 = 0.2;
+    // Lets the average settle after a change, so that the quality does not oscillate
+    const COOLDOWN: f32 = 2.0;
+
+    /// Accounts for a frame that took `frame_time` seconds, `grid_time` of which
+    /// were spent on the grids, and returns whether the quality changed. Does
+    /// nothing but track the grid time when disabled.
+    pub fn update(&mut self, frame_time: f32, grid_time: f32) -> bool {
+        self.smoothed_grid_time += Self::SMOOTHING * (grid_time - self.smoothed_grid_time);
+        self.cooldown = (self.cooldown - frame_time).max(0.0);
+        if !self.enabled || self.cooldown > 0.0 {
+            return false;
+        }
+        let new_quality = if self.smoothed_grid_time > self.grid_budget {
+            self.quality.lower()
+        } else if self.smoothed_grid_time < Self::RAISE_THRESHOLD * self.grid_budget {
+            self.quality.higher()
+        } else {
+            None
+        };
+        match new_quality {
+            Some(quality) => {
+                self.quality = quality;
+                self.cooldown = Self::COOLDOWN;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Switches to the next quality by hand, which turns the automatic setting off.
+    pub fn next(&mut self) {
+        self.enabled = false;
+        self.quality = self.quality.next();
+    }
+
+    pub fn toggle(&mut self) {
+        self.enabled = !self.enabled;
+        self.cooldown = Self::COOLDOWN;
+    }
+}
+
+impl fmt::Display for AutoQuality {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.enabled {
+            write!(f, "{} (auto)", self.quality)
+        } else {
+            write!(f, "{}", self.quality)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A frame at 60 FPS under vsync
+    const VSYNC_FRAME: f32 = 1.0 / 60.0;
+
+    fn run(auto_quality: &mut AutoQuality, seconds: f32, frame_time: f32, grid_time: f32) {
+        for _ in 0..(seconds / frame_time).ceil() as usize {
+            auto_quality.update(frame_time, grid_time);
+        }
+    }
+
+    #[test]
+    fn qualities_step_between_full_and_quarter_resolution() {
+        assert_eq!(Quality::Full.lower(), Some(Quality::Half));
+        assert_eq!(Quality::Quarter.lower(), None);
+        assert_eq!(Quality::Full.higher(), None);
+        for &quality in Quality::ALL {
+            if let Some(lower) = quality.lower() {
+                assert_eq!(lower.higher(), Some(quality));
+                assert_eq!(lower.resolution(), quality.resolution() / 2.0);
+            }
+        }
+    }
+
+    #[test]
+    fn quality_drops_while_the_grids_are_over_budget() {
+        let mut auto_quality = AutoQuality::default();
+        run(&mut auto_quality, 10.0, 0.05, 0.04);
+        assert_eq!(auto_quality.quality, Quality::Quarter);
+    }
+
+    #[test]
+    fn quality_recovers_under_vsync_after_a_spike() {
+        let mut auto_quality = AutoQuality::default();
+        run(&mut auto_quality, 3.0, 0.05, 0.04);
+        assert_ne!(auto_quality.quality, Quality::Full);
+        run(&mut auto_quality, 10.0, VSYNC_FRAME, 0.001);
+        assert_eq!(auto_quality.quality, Quality::Full);
+    }
+
+    #[test]
+    fn quality_holds_within_the_budget() {
+        let mut auto_quality = AutoQuality { quality: Quality::Half, ..AutoQuality::default() };
+        run(&mut auto_quality, 10.0, VSYNC_FRAME, 0.5 * AutoQuality::DEFAULT_GRID_BUDGET);
+        assert_eq!(auto_quality.quality, Quality::Half);
+    }
+
+    #[test]
+    fn disabled_quality_never_changes() {
+        let mut auto_quality = AutoQuality::default();
+        auto_quality.toggle();
+        run(&mut auto_quality, 10.0, 0.05, 0.04);
+        assert_eq!(auto_quality.quality, Quality::Full);
+    }
+}
@@ -0,0 +1,251 @@
+use crate::charges::PointCharge;
+use crate::collisions::CollisionPolicy;
+use crate::integrators::IntegratorKind;
+use crate::simulation::Simulation;
+use crate::units::Units;
+use macroquad::color::Color;
+use macroquad::math::Vec2;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use std::{fmt, fs, io};
+
+#[derive(Debug)]
+pub enum SceneError {
+    Io(io::Error),
+    Format(serde_json::Error),
+    UnsupportedVersion(u32),
+}
+
+impl fmt::Display for SceneError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SceneError::Io(error) => write!(f, "cannot access scene file: {error}"),
+            SceneError::Format(error) => write!(f, "malformed scene: {error}"),
+            SceneError::UnsupportedVersion(version) => write!(f, "unsupported scene version {version} (latest is {})", Scene::VERSION),
+        }
+    }
+}
+
+impl std::error::Error for SceneError {}
+
+impl From<io::Error> for SceneError {
+    fn from(error: io::Error) -> Self {
+        SceneError::Io(error)
+    }
+}
+
+impl From<serde_json::Error> for SceneError {
+    fn from(error: serde_json::Error) -> Self {
+        SceneError::Format(error)
+    }
+}
+
+/// A saved charge. Positions are in pixels and velocities in pixels per second.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ChargeRecord {
+    pub id: usize,
+    pub position: [f32; 2],
+    pub velocity: [f32; 2],
+    pub charge: f32,
+    pub mass: f32,
+    pub is_fixed: bool,
+    /// Color overriding the one derived from the charge, if any. Missing from
+    /// version 1 scenes, whose charges always take the derived color.
+    #[serde(default)]
+    pub color: Option<[f32; 4]>,
+}
+
+impl ChargeRecord {
+    #[must_use]
+    pub fn capture(charge: &PointCharge) -> Self {
+        ChargeRecord {
+            id: charge.id,
+            position: charge.center.to_array(),
+            velocity: charge.velocity.to_array(),
+            charge: charge.q(),
+            mass: charge.m,
+            is_fixed: charge.is_fixed,
+            color: charge.color().map(|color| [color.r, color.g, color.b, color.a]),
+        }
+    }
+
+    #[must_use]
+    pub fn restore(&self) -> PointCharge {
+        let mut charge = PointCharge::new(self.id, Vec2::from_array(self.position), self.charge, self.is_fixed);
+        charge.velocity = Vec2::from_array(self.velocity);
+        charge.m = self.mass;
+        charge.set_color(self.color.map(|[r, g, b, a]| Color::new(r, g, b, a)));
+        charge
+    }
+}
+
+/// Simulation settings stored alongside the charges.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct SceneSettings {
+    pub units: Units,
+    pub integrator: IntegratorKind,
+    pub damping: f32,
+    pub step: f32,
+    pub substeps: u32,
+    pub speed: f32,
+    pub adaptive_step: bool,
+    /// Missing from scenes saved before collision policies were configurable.
+    #[serde(default)]
+    pub collisions: CollisionPolicy,
+}
+
+impl SceneSettings {
+    #[must_use]
+    pub fn capture(simulation: &Simulation) -> Self {
+        SceneSettings {
+            units: simulation.units,
+            integrator: simulation.integrator,
+            damping: simulation.damping,
+            step: simulation.timestep.step,
+            substeps: simulation.timestep.substeps,
+            speed: simulation.timestep.speed,
+            adaptive_step: simulation.adaptive_step.enabled,
+            collisions: simulation.collisions,
+        }
+    }
+
+    pub fn apply(&self, simulation: &mut Simulation) {
+        simulation.units = self.units;
+        simulation.integrator = self.integrator;
+        simulation.damping = self.damping;
+        simulation.timestep.step = self.step;
+        simulation.timestep.substeps = self.substeps.max(1);
+        simulation.timestep.speed = self.speed;
+        simulation.adaptive_step.enabled = self.adaptive_step;
+        simulation.collisions = self.collisions;
+    }
+}
+
+/// Everything needed to rebuild a scene: charges, the voltmeter's
+/// equipotentials and simulation settings, stored as versioned JSON.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Scene {
+    pub version: u32,
+    pub charges: Vec<ChargeRecord>,
+    pub equipotentials: Vec<f32>,
+    pub settings: SceneSettings,
+}
+
+impl Scene {
+    /// Version 2 added color overrides and collision policies.
+    pub const VERSION: u32 = 2;
+    pub const DEFAULT_FILE_NAME: &'static str = "scene.json";
+
+    #[must_use]
+    pub fn capture(simulation: &Simulation, equipotentials: &[f32]) -> Self {
+        Scene {
+            version: Self::VERSION,
+            charges: simulation.charges.iter().map(ChargeRecord::capture).collect(),
+            equipotentials: equipotentials.to_vec(),
+            settings: SceneSettings::capture(simulation),
+        }
+    }
+
+    /// Replaces every charge and setting of `simulation` with the scene's.
+    pub fn apply(&self, simulation: &mut Simulation) {
+        simulation.clear();
+        self.settings.apply(simulation);
+        for record in &self.charges {
+            simulation.add_charge(record.restore());
+        }
+    }
+
+    /// # Errors
+    /// Fails if the scene cannot be serialized.
+    pub fn to_json(&self) -> Result<String, SceneError> {
+        Ok(serde_json::to_string_pretty(self)?)
+    }
+
+    /// Parses a scene of any version up to the current one, upgrading it to the current one.
+    ///
+    /// # Errors
+    /// Fails on malformed JSON or on a version newer than this build understands.
+    pub fn from_json(json: &str) -> Result<Self, SceneError> {
+        let mut scene: Scene = serde_json::from_str(json)?;
+        if scene.version > Self::VERSION {
+            return Err(SceneError::UnsupportedVersion(scene.version));
+        }
+        if scene.version < 2 {
+            for record in &mut scene.charges {
+                record.color = None;
+            }
+        }
+        scene.version = Self::VERSION;
+        Ok(scene)
+    }
+
+    /// # Errors
+    /// Fails if the file cannot be written.
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), SceneError> {
+        fs::write(path, self.to_json()?)?;
+        Ok(())
+    }
+
+    /// # Errors
+    /// Fails if the file cannot be read or does not hold a valid scene.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, SceneError> {
+        Self::from_json(&fs::read_to_string(path)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::collisions::CollisionKind;
+    use macroquad::color::ORANGE;
+
+    fn scene() -> Scene {
+        let mut simulation = Simulation::new(400, 400);
+        simulation.units = Units::si(50.0);
+        simulation.integrator = IntegratorKind::RungeKutta4;
+        simulation.damping = 0.5;
+        simulation.collisions.like_signs = CollisionKind::Elastic;
+        let moving = simulation.spawn_charge(Vec2::new(100.0, 120.0), PointCharge::DEFAULT_CHARGE, false);
+        simulation.charge_by_id_mut(moving).unwrap().velocity = Vec2::new(30.0, -10.0);
+        let colored = simulation.spawn_charge(Vec2::new(300.0, 200.0), -2.0 * PointCharge::DEFAULT_CHARGE, true);
+        simulation.charge_by_id_mut(colored).unwrap().set_color(Some(ORANGE));
+        Scene::capture(&simulation, &[-1.5, 2.0])
+    }
+
+    #[test]
+    fn scene_survives_a_save_and_load() {
+        let scene = scene();
+        let path = std::env::temp_dir().join(format!("scene-round-trip-{}.json", std::process::id()));
+        scene.save(&path).unwrap();
+        let loaded = Scene::load(&path);
+        fs::remove_file(&path).unwrap();
+        let loaded = loaded.unwrap();
+        assert_eq!(loaded, scene);
+
+        // And restores the same simulation
+        let mut simulation = Simulation::new(400, 400);
+        loaded.apply(&mut simulation);
+        assert_eq!(Scene::capture(&simulation, &loaded.equipotentials), scene);
+        assert_eq!(simulation.charges[1].color(), Some(ORANGE));
+    }
+
+    #[test]
+    fn version_1_scenes_load_with_derived_colors_and_default_collisions() {
+        let mut json: serde_json::Value = serde_json::from_str(&scene().to_json().unwrap()).unwrap();
+        json["version"] = 1.into();
+        json["settings"].as_object_mut().unwrap().remove("collisions");
+        let loaded = Scene::from_json(&json.to_string()).unwrap();
+
+        assert_eq!(loaded.version, Scene::VERSION);
+        assert!(loaded.charges.iter().all(|record| record.color.is_none()));
+        assert_eq!(loaded.settings.collisions, CollisionPolicy::default());
+        assert_eq!(loaded.charges.len(), 2);
+    }
+
+    #[test]
+    fn newer_scenes_are_rejected() {
+        let mut scene = scene();
+        scene.version = Scene::VERSION + 1;
+        assert!(matches!(Scene::from_json(&scene.to_json().unwrap()), Err(SceneError::UnsupportedVersion(version)) if version == Scene::VERSION + 1));
+    }
+}
@@ -0,0 +1,805 @@
+use crate::barnes_hut::QuadTree;
+use crate::charges::Sign::Neutral;
+use crate::collisions::{find_contacts, separate_and_bounce, CollisionPolicy, Contact, Outcome, SpatialHash};
+use crate::charges::{calculate_potential, coulomb_force, PointCharge, TestCharge};
+use crate::diagnostics::{Diagnostics, EnergyReport};
+use crate::integrators::{IntegratorKind, PhaseState};
+use crate::species::Species;
+use crate::timestep::{AdaptiveStep, FixedTimestep};
+use crate::units::Units;
+use itertools::Itertools;
+use macroquad::math::{Rect, Vec2};
+use ndarray::{Array, Array2, Zip};
+use rayon::prelude::*;
+use std::cell::RefCell;
+use std::collections::HashMap;
+
+/// Two charges that collided and were replaced, e.g. merged into a single one.
+#[derive(Clone, Debug)]
+pub struct MergeEvent {
+    pub originals: [PointCharge; 2],
+    /// Charges replacing the originals, none if they annihilated.
+    pub merged: Vec<PointCharge>,
+}
+
+/// A charge as last added into the potential grid.
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct PotentialSource {
+    id: usize,
+    center: Vec2,
+    q: f32,
+}
+
+impl PotentialSource {
+    fn of(charge: &PointCharge) -> Self {
+        PotentialSource { id: charge.id, center: charge.center, q: charge.q() }
+    }
+
+    /// Potential at `point`, kept finite at the source itself so that it can be
+    /// subtracted back out of the grid.
+    fn contribution_at(&self, point: Vec2, units: &Units) -> f32 {
+        units.potential(self.q, self.center.distance(point).max(Simulation::MIN_POTENTIAL_DISTANCE))
+    }
+}
+
+/// What the potential grid currently holds, so it can be updated incrementally.
+#[derive(Clone, Debug)]
+struct PotentialCache {
+    /// Sources by charge id.
+    sources: HashMap<usize, PotentialSource>,
+    units: Units,
+    /// Incremental updates since the grid was last recomputed from scratch.
+    incremental_updates: u32,
+}
+
+/// Headless simulation engine: owns the point charges together with the
+/// test-charge grid and the potential grid, and advances them in time without
+/// issuing any drawing call, so it can run in batch jobs, tests or servers.
+pub struct Simulation {
+    pub charges: Vec<PointCharge>,
+    pub test_charges: Vec<TestCharge>,
+    pub potentials: Array2<(Vec2, f32)>,
+    /// Electric field at each potential sample, as the force on a unit charge;
+    /// only refreshed by `update_field_grid`.
+    pub fields: Array2<Vec2>,
+    pub units: Units,
+    pub integrator: IntegratorKind,
+    /// Velocity damping coefficient, in 1/s; zero conserves energy and momentum.
+    pub damping: f32,
+    /// Opening angle of the Barnes–Hut trees approximating forces, fields and
+    /// potentials in crowded scenes; zero makes them exact.
+    pub opening_angle: f32,
+    /// Whether each charge keeps every pairwise force acting on it, for drawing
+    /// them one by one, rather than only the net force. Ignored in crowded scenes.
+    pub individual_forces: bool,
+    pub collisions: CollisionPolicy,
+    pub timestep: FixedTimestep,
+    pub adaptive_step: AdaptiveStep,
+    pub diagnostics: Diagnostics,
+    time: f32,
+    last_step: f32,
+    merges: Vec<MergeEvent>,
+    potential_cache: Option<PotentialCache>,
+    view: Rect,
+    width: u16,
+    height: u16,
+    resolution: f32,
+    next_id: usize,
+}
+
+impl Simulation {
+    pub const ELECTRIC_FIELD_DENSITY: usize = 25;
+    // Loses about 5% of the velocity per frame at 60 FPS, so no integrator conserves energy by default
+    pub const DEFAULT_DAMPING: f32 = 3.0;
+    const PADDING_FROM_BORDERS: u16 = 0;
+    // Distance, in pixels, below which potential samples see a charge as if at this distance
+    const MIN_POTENTIAL_DISTANCE: f32 = 0.5;
+    // Incremental potential updates after which the grid is recomputed, to drop rounding errors
+    const MAX_INCREMENTAL_POTENTIAL_UPDATES: u32 = 240;
+    // Charges from which Barnes–Hut trees are used; below, direct sums are as fast and exact
+    pub const BARNES_HUT_MIN_CHARGES: usize = 64;
+
+    /// Creates an empty simulation drawn on a `width` × `height` pixels screen,
+    /// with one potential sample per pixel, initially covering the world from the
+    /// origin to (`width`, `height`).
+    #[must_use]
+    pub fn new(width: u16, height: u16) -> Self {
+        let view = Rect::new(0.0, 0.0, f32::from(width), f32::from(height));
+        let mut simulation = Simulation {
+            charges: vec![],
+            test_charges: vec![],
+            potentials: Array2::default((0, 0)),
+            fields: Array2::default((0, 0)),
+            units: Units::default(),
+            integrator: IntegratorKind::default(),
+            damping: Self::DEFAULT_DAMPING,
+            opening_angle: QuadTree::DEFAULT_OPENING_ANGLE,
+            individual_forces: false,
+            collisions: CollisionPolicy::default(),
+            timestep: FixedTimestep::default(),
+            adaptive_step: AdaptiveStep::default(),
+            diagnostics: Diagnostics::new(),
+            time: 0.0,
+            last_step: 0.0,
+            merges: vec![],
+            potential_cache: None,
+            view,
+            width,
+            height,
+            resolution: 1.0,
+            next_id: 1,
+        };
+        simulation.reallocate_grids();
+        simulation
+    }
+
+    #[must_use]
+    pub fn width(&self) -> u16 {
+        self.width
+    }
+
+    #[must_use]
+    pub fn height(&self) -> u16 {
+        self.height
+    }
+
+    /// Changes the size of the screen the grids are drawn on, e.g. when the window
+    /// is resized, keeping the region they cover.
+    pub fn resize(&mut self, width: u16, height: u16) {
+        if (width, height) == (self.width, self.height) {
+            return;
+        }
+        self.width = width;
+        self.height = height;
+        self.reallocate_grids();
+    }
+
+    /// Potential and field samples per screen pixel, along each axis.
+    #[must_use]
+    pub fn resolution(&self) -> f32 {
+        self.resolution
+    }
+
+    /// Changes the number of potential and field samples per screen pixel.
+    pub fn set_resolution(&mut self, resolution: f32) {
+        if resolution != self.resolution {
+            self.resolution = resolution;
+            self.reallocate_grids();
+        }
+    }
+
+    /// Number of potential and field samples along x and y.
+    #[must_use]
+    pub fn grid_size(&self) -> (usize, usize) {
+        #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+        let samples = |pixels: u16| ((f32::from(pixels) * self.resolution).ceil() as usize).max(1);
+        (samples(self.width), samples(self.height))
+    }
+
+    fn reallocate_grids(&mut self) {
+        let size = self.grid_size();
+        self.potentials = Array::from_elem(size, (Vec2::ZERO, 0.0f32));
+        self.fields = Array::from_elem(size, Vec2::ZERO);
+        self.resample();
+    }
+
+    /// Region of the world covered by the field and potential grids.
+    #[must_use]
+    pub fn view(&self) -> Rect {
+        self.view
+    }
+
+    /// Moves the field and potential grids over `view`, keeping their resolution.
+    pub fn set_view(&mut self, view: Rect) {
+        if view != self.view {
+            self.view = view;
+            self.resample();
+        }
+    }
+
+    /// Places the test charges and the potential samples evenly over the view,
+    /// the latter at the centers of the texels of a heatmap covering it so that
+    /// it can be upsampled with bilinear filtering.
+    fn resample(&mut self) {
+        self.potential_cache = None;
+        let view = self.view;
+        let scale = Vec2::new(view.w / f32::from(self.width), view.h / f32::from(self.height));
+        let field_x_points = (Self::PADDING_FROM_BORDERS..=self.width - Self::PADDING_FROM_BORDERS).step_by(Self::ELECTRIC_FIELD_DENSITY);
+        let field_y_points = (Self::PADDING_FROM_BORDERS..=self.height - Self::PADDING_FROM_BORDERS).step_by(Self::ELECTRIC_FIELD_DENSITY);
+        self.test_charges = field_x_points.cartesian_product(field_y_points)
+            .map(|(x, y)| TestCharge::new(view.point() + Vec2::new(f32::from(x), f32::from(y)) * scale))
+            .collect();
+
+        let (columns, rows) = self.potentials.dim();
+        let sample_scale = Vec2::new(view.w / columns as f32, view.h / rows as f32);
+        Zip::indexed(&mut self.potentials).par_for_each(|(x, y), (point, _potential)| {
+            *point = view.point() + (Vec2::new(x as f32, y as f32) + 0.5) * sample_scale;
+        });
+    }
+
+    /// Returns a fresh identifier for a charge about to be added.
+    pub fn next_id(&mut self) -> usize {
+        let id = self.next_id;
+        self.next_id += 1;
+        id
+    }
+
+    pub fn add_charge(&mut self, charge: PointCharge) {
+        self.next_id = self.next_id.max(charge.id + 1);
+        self.charges.push(charge);
+    }
+
+    /// Spawns a charge carrying an arbitrary signed charge `q`, returning its id.
+    pub fn spawn_charge(&mut self, center: Vec2, q: f32, is_fixed: bool) -> usize {
+        let id = self.next_id();
+        self.charges.push(PointCharge::new(id, center, q, is_fixed));
+        id
+    }
+
+    /// Spawns an instance of `species`, with its charge, mass and color,
+    /// returning its id.
+    pub fn spawn_species(&mut self, species: &Species, center: Vec2, is_fixed: bool) -> usize {
+        let id = self.next_id();
+        self.charges.push(species.spawn(id, center, is_fixed));
+        id
+    }
+
+    #[must_use]
+    pub fn charge_by_id(&self, id: usize) -> Option<&PointCharge> {
+        self.charges.iter().find(|charge| charge.id == id)
+    }
+
+    pub fn charge_by_id_mut(&mut self, id: usize) -> Option<&mut PointCharge> {
+        self.charges.iter_mut().find(|charge| charge.id == id)
+    }
+
+    /// Removes the charge with the given id, returning it if it existed.
+    pub fn remove_charge(&mut self, id: usize) -> Option<PointCharge> {
+        let index = self.charges.iter().position(|charge| charge.id == id)?;
+        Some(self.charges.remove(index))
+    }
+
+    /// Adds a copy of the charge with the given id next to it, returning the
+    /// id of the copy.
+    pub fn duplicate_charge(&mut self, id: usize) -> Option<usize> {
+        let mut copy = self.charge_by_id(id)?.clone();
+        copy.id = self.next_id();
+        copy.set_center(copy.center + Vec2::new(2.5 * copy.drawing_circle.radius, 0.0));
+        let copy_id = copy.id;
+        self.charges.push(copy);
+        Some(copy_id)
+    }
+
+    pub fn spawn_positive_charge(&mut self, center: Vec2, is_fixed: bool) {
+        let id = self.next_id();
+        self.charges.push(PointCharge::new_positive_charge(id, center, is_fixed));
+    }
+
+    pub fn spawn_negative_charge(&mut self, center: Vec2, is_fixed: bool) {
+        let id = self.next_id();
+        self.charges.push(PointCharge::new_negative_charge(id, center, is_fixed));
+    }
+
+    /// Removes every charge and forgets the diagnostics history.
+    pub fn clear(&mut self) {
+        self.charges.clear();
+        self.diagnostics.clear();
+    }
+
+    /// Simulated seconds elapsed since the simulation was created.
+    #[must_use]
+    pub fn time(&self) -> f32 {
+        self.time
+    }
+
+    /// Length of the most recent step, in simulated seconds.
+    #[must_use]
+    pub fn last_step(&self) -> f32 {
+        self.last_step
+    }
+
+    /// Merges that happened during the last call to `advance` or `step`, oldest
+    /// first; they are handed over only once.
+    pub fn take_merges(&mut self) -> Vec<MergeEvent> {
+        std::mem::take(&mut self.merges)
+    }
+
+    /// Index of the charge whose circle contains `point`, if any.
+    #[must_use]
+    pub fn charge_index_at(&self, point: Vec2) -> Option<usize> {
+        self.charges.iter().position(|charge| charge.drawing_circle.contains(point))
+    }
+
+    #[must_use]
+    pub fn potential_at(&self, point: Vec2) -> f32 {
+        calculate_potential(&point, &self.charges, &self.units)
+    }
+
+    /// Accounts for `frame_time` real seconds by running as many fixed-length
+    /// steps as are due, each split into sub-steps, which the adaptive step
+    /// controller may split further during close encounters.
+    pub fn advance(&mut self, frame_time: f32) {
+        let mut merges = vec![];
+        let steps = self.timestep.accumulate(frame_time);
+        let substep = self.timestep.substep();
+        for _ in 0..steps * self.timestep.substeps {
+            if self.adaptive_step.enabled {
+                let mut remaining = substep;
+                while remaining > 0.0 {
+                    let delta = self.adaptive_step.next_step(remaining, self.timestep.step, &self.charges);
+                    self.step(delta);
+                    merges.append(&mut self.merges);
+                    remaining -= delta;
+                }
+            } else {
+                self.step(substep);
+                merges.append(&mut self.merges);
+            }
+        }
+        self.merges = merges;
+    }
+
+    /// Advances every charge by `delta` seconds, resolving collisions and merges.
+    pub fn step(&mut self, delta: f32) {
+        self.merges.clear();
+        self.resolve_collisions();
+
+        let mut states: Vec<PhaseState> = self.charges.iter().map(PointCharge::phase_state).collect();
+        // Net forces of the integrator's last evaluation, kept for the force arrows
+        let last_forces = RefCell::new(vec![]);
+        let acceleration = |states: &[PhaseState]| {
+            let forces = net_forces(&self.charges, states, &self.units, self.opening_angle);
+            let accelerations = accelerations(&self.charges, states, &forces, &self.units, self.damping);
+            *last_forces.borrow_mut() = forces;
+            accelerations
+        };
+        self.integrator.integrator().integrate(&mut states, delta, &acceleration);
+        for (charge, state) in self.charges.iter_mut().zip(states) {
+            charge.set_phase_state(state);
+        }
+
+        if self.individual_forces {
+            self.update_forces();
+        } else {
+            // Evaluated within the step rather than at its end, which is close
+            // enough for drawing and saves evaluating the forces again
+            self.apply_net_forces(last_forces.into_inner());
+        }
+        self.time += delta;
+        self.last_step = delta;
+        if self.diagnostics.is_due(self.time) {
+            self.diagnostics.record(EnergyReport::measure(&self.charges, &self.units, self.time, self.opening_angle));
+        }
+    }
+
+    /// Barnes–Hut tree over the charges, when there are enough of them for it to pay off.
+    fn charge_tree(&self) -> Option<QuadTree> {
+        (self.charges.len() >= Self::BARNES_HUT_MIN_CHARGES)
+            .then(|| QuadTree::new(self.charges.iter().map(|charge| (charge.center, charge.q())), self.opening_angle))
+    }
+
+    /// Recomputes the forces acting on each charge, as shown by the force arrows.
+    /// Only the net force is kept unless `individual_forces` is set, and in
+    /// crowded scenes it is approximated by a Barnes–Hut tree.
+    ///
+    /// The forces are computed in parallel from a read-only view of the charges,
+    /// then applied to them.
+    pub fn update_forces(&mut self) {
+        let tree = self.charge_tree();
+        let charges = &self.charges;
+        let units = &self.units;
+        let individual_forces = self.individual_forces;
+        let accumulated: Vec<(Vec<Vec2>, Vec2)> = charges.par_iter().enumerate()
+            .map(|(i, charge)| match &tree {
+                Some(tree) => (vec![], charge.q() * tree.field_at(charge.center, units, Some(i))),
+                None => {
+                    let forces = charges.iter().enumerate()
+                        .filter(|(j, _)| *j != i)
+                        .map(|(_, other)| coulomb_force(charge.center, charge.q(), other.center, other.q(), units));
+                    if individual_forces {
+                        let forces: Vec<Vec2> = forces.collect();
+                        let net_force = forces.iter().sum();
+                        (forces, net_force)
+                    } else {
+                        (vec![], forces.sum())
+                    }
+                }
+            })
+            .collect();
+
+        for (charge, (forces, net_force)) in self.charges.iter_mut().zip(accumulated) {
+            charge.set_forces(forces, net_force);
+            charge.calculate_max_force();
+            charge.calculate_acceleration(&self.units);
+        }
+    }
+
+    /// Sets the net force acting on each charge, leaving out the individual forces.
+    fn apply_net_forces(&mut self, forces: Vec<Vec2>) {
+        for (charge, net_force) in self.charges.iter_mut().zip(forces) {
+            charge.set_forces(vec![], net_force);
+            charge.calculate_max_force();
+            charge.calculate_acceleration(&self.units);
+        }
+    }
+
+    /// Applies the collision policy to every pair of overlapping charges.
+    ///
+    /// The outcome does not depend on the order of the charges: contacts are all
+    /// found before any is resolved, a charge touching several others that it
+    /// would be replaced with is replaced along with the one it overlaps most,
+    /// ties going to the lowest ids, and the remaining contacts are resolved
+    /// together.
+    fn resolve_collisions(&mut self) {
+        let contacts = find_contacts(&self.charges);
+        let charges = &mut self.charges;
+
+        let ids = |contact: &Contact| {
+            let (first, second) = (charges[contact.first].id, charges[contact.second].id);
+            (first.min(second), first.max(second))
+        };
+        let mut bouncing: Vec<(Contact, f32)> = vec![];
+        let mut replacing: Vec<(Contact, Vec<PointCharge>)> = vec![];
+        for contact in &contacts {
+            match self.collisions.outcome(&charges[contact.first], &charges[contact.second]) {
+                Outcome::Bounce { restitution } => bouncing.push((*contact, restitution)),
+                Outcome::PassThrough => {}
+                Outcome::Replace(replacements) => replacing.push((*contact, replacements)),
+            }
+        }
+        replacing.sort_by(|(a, _), (b, _)| b.overlap.total_cmp(&a.overlap).then_with(|| ids(a).cmp(&ids(b))));
+
+        let mut is_replaced = vec![false; charges.len()];
+        let mut new_charges = Vec::new();
+        for (contact, replacements) in replacing {
+            if is_replaced[contact.first] || is_replaced[contact.second] {
+                continue;
+            }
+            is_replaced[contact.first] = true;
+            is_replaced[contact.second] = true;
+            self.merges.push(MergeEvent {
+                originals: [charges[contact.first].clone(), charges[contact.second].clone()],
+                merged: replacements.clone(),
+            });
+            new_charges.extend(replacements);
+        }
+
+        bouncing.retain(|(contact, _)| !is_replaced[contact.first] && !is_replaced[contact.second]);
+        separate_and_bounce(charges, &bouncing);
+
+        // Remove replaced charges, keeping the others in order
+        let mut index = 0;
+        charges.retain(|_| {
+            index += 1;
+            !is_replaced[index - 1]
+        });
+
+        charges.extend(new_charges);
+    }
+
+    /// Recomputes the electric field sampled by the test-charge grid, in a
+    /// parallel read-only pass whose results are then applied to the grid.
+    pub fn update_field(&mut self) {
+        let tree = self.charge_tree();
+        let charges = &self.charges;
+        let units = &self.units;
+        // Arrows are hidden within these circles around the charges
+        let hiding_circles: Vec<(Vec2, f32)> = charges.iter()
+            .map(|charge| (charge.center, 1.5 * charge.drawing_circle.radius))
+            .collect();
+        let hash = SpatialHash::with_circles(&hiding_circles);
+        // Field at each test charge, `None` for those hidden under a charge
+        let fields: Vec<Option<Vec2>> = self.test_charges.par_iter()
+            .map(|test_charge| {
+                let is_hidden = hash.circles_near(test_charge.center).any(|i| {
+                    let (center, radius) = hiding_circles[i];
+                    test_charge.center.distance_squared(center) < radius.powi(2)
+                });
+                (!is_hidden).then(|| match &tree {
+                    Some(tree) => tree.field_at(test_charge.center, units, None),
+                    None => charges.iter()
+                        .map(|charge| coulomb_force(test_charge.center, 1.0, charge.center, charge.q(), units))
+                        .sum(),
+                })
+            })
+            .collect();
+
+        let test_charges = &mut self.test_charges;
+        for (test_charge, field) in test_charges.iter_mut().zip(fields) {
+            test_charge.clear_forces();
+            test_charge.is_hidden = field.is_none();
+            if let Some(field) = field {
+                test_charge.net_force = field;
+            }
+        }
+
+        let max_magnitude: f32 = test_charges.iter()
+            .map(|charge| charge.net_force.length())
+            .max_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal))
+            .unwrap_or(f32::INFINITY);
+        for test_charge in test_charges.iter_mut() {
+            test_charge.set_max_force(max_magnitude);
+            test_charge.update_arrow();
+        }
+    }
+
+    /// Recomputes the electric field at every potential sample, in parallel.
+    pub fn update_field_grid(&mut self) {
+        let units = &self.units;
+        if let Some(tree) = self.charge_tree() {
+            Zip::from(&mut self.fields).and(&self.potentials).par_for_each(|field, (point, _potential)| {
+                *field = tree.field_at(*point, units, None);
+            });
+            return;
+        }
+        let charges = &self.charges;
+        Zip::from(&mut self.fields).and(&self.potentials).par_for_each(|field, (point, _potential)| {
+            *field = charges.iter()
+                .filter(|charge| charge.sign != Neutral)
+                .map(|charge| coulomb_force(*point, 1.0, charge.center, charge.q(), units))
+                .sum();
+        });
+    }
+
+    /// Brings the potential grid up to date and returns whether it changed.
+    ///
+    /// Only the charges that were added, removed, moved or changed since the
+    /// last call are subtracted from or added to the grid, and nothing is done
+    /// when the scene is static. The grid is recomputed from scratch after the
+    /// view or the units change, when most charges changed, and every so often
+    /// to drop accumulated rounding errors.
+    pub fn update_potential(&mut self) -> bool {
+        let sources: Vec<PotentialSource> = self.charges.iter()
+            .filter(|charge| charge.sign != Neutral)
+            .map(PotentialSource::of)
+            .collect();
+
+        let Some(cache) = self.potential_cache.as_mut()
+            .filter(|cache| cache.units == self.units && cache.incremental_updates < Self::MAX_INCREMENTAL_POTENTIAL_UPDATES) else {
+            self.recompute_potential(sources);
+            return true;
+        };
+        let current: HashMap<usize, PotentialSource> = sources.iter().map(|source| (source.id, *source)).collect();
+        let mut removed: Vec<PotentialSource> = cache.sources.values()
+            .filter(|source| current.get(&source.id) != Some(source))
+            .copied()
+            .collect();
+        let added: Vec<PotentialSource> = sources.iter()
+            .filter(|source| cache.sources.get(&source.id) != Some(source))
+            .copied()
+            .collect();
+        if removed.is_empty() && added.is_empty() {
+            return false;
+        }
+        if removed.len() + added.len() >= sources.len() {
+            // As expensive as starting over
+            self.recompute_potential(sources);
+            return true;
+        }
+
+        // In a fixed order, so that the grid does not depend on the hashing
+        removed.sort_unstable_by_key(|source| source.id);
+        cache.sources = current;
+        cache.incremental_updates += 1;
+        let units = &self.units;
+        self.potentials.par_map_inplace(|(point, potential)| {
+            for source in &removed {
+                *potential -= source.contribution_at(*point, units);
+            }
+            for source in &added {
+                *potential += source.contribution_at(*point, units);
+            }
+        });
+        true
+    }
+
+    fn recompute_potential(&mut self, sources: Vec<PotentialSource>) {
+        let units = &self.units;
+        if sources.len() >= Self::BARNES_HUT_MIN_CHARGES {
+            let tree = QuadTree::new(sources.iter().map(|source| (source.center, source.q)), self.opening_angle);
+            self.potentials.par_map_inplace(|(point, potential)| {
+                *potential = tree.potential_at(*point, units, None, Self::MIN_POTENTIAL_DISTANCE);
+            });
+        } else {
+            self.potentials.par_map_inplace(|(point, potential)| {
+                *potential = sources.iter().map(|source| source.contribution_at(*point, units)).sum();
+            });
+        }
+        let sources = sources.into_iter().map(|source| (source.id, source)).collect();
+        self.potential_cache = Some(PotentialCache { sources, units: self.units, incremental_updates: 0 });
+    }
+}
+
+/// Net Coulomb force on each of `charges` when placed in `states`, from all the
+/// other charges, approximated by a Barnes–Hut tree with `opening_angle` in
+/// crowded scenes.
+fn net_forces(charges: &[PointCharge], states: &[PhaseState], units: &Units, opening_angle: f32) -> Vec<Vec2> {
+    let tree = (charges.len() >= Simulation::BARNES_HUT_MIN_CHARGES)
+        .then(|| QuadTree::new(charges.iter().zip(states).map(|(charge, state)| (state.position, charge.q())), opening_angle));
+    charges.par_iter().zip(states).enumerate().map(|(i, (charge, state))| match &tree {
+        Some(tree) => charge.q() * tree.field_at(state.position, units, Some(i)),
+        None => charges.iter().zip(states).enumerate()
+            .filter(|(j, _)| *j != i)
+            .map(|(_, (other, other_state))| coulomb_force(state.position, charge.q(), other_state.position, other.q(), units))
+            .sum(),
+    }).collect()
+}
+
+/// Accelerations of `charges` when placed in `states` and subject to `forces`,
+/// minus velocity damping. Fixed charges never accelerate.
+fn accelerations(charges: &[PointCharge], states: &[PhaseState], forces: &[Vec2], units: &Units, damping: f32) -> Vec<Vec2> {
+    let pixels_per_metre = units.metres_to_pixels(1.0);
+    charges.iter().zip(states).zip(forces).map(|((charge, state), force)| {
+        if charge.is_fixed {
+            return Vec2::ZERO;
+        }
+        pixels_per_metre * *force / charge.m - damping * state.velocity
+    }).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::collisions::CollisionKind;
+    use crate::quality::Quality;
+
+    /// A tight cluster of overlapping charges of both signs, moving in all directions.
+    fn cluster(simulation: &mut Simulation) -> Vec<PointCharge> {
+        (0..12)
+            .map(|i| {
+                let angle = i as f32;
+                let center = Vec2::new(100.0, 100.0) + 6.0 * i as f32 * Vec2::from_angle(angle);
+                let q = if i % 3 == 0 { -1.0 } else { 1.0 } * PointCharge::DEFAULT_CHARGE;
+                let mut charge = PointCharge::new(simulation.next_id(), center, q, i == 5);
+                if !charge.is_fixed {
+                    charge.velocity = 20.0 * Vec2::from_angle(2.0 * angle);
+                }
+                charge
+            })
+            .collect()
+    }
+
+    /// Resolves the collisions among `charges`, returning them sorted by id.
+    fn resolved(charges: Vec<PointCharge>) -> Vec<PointCharge> {
+        let mut simulation = Simulation::new(200, 200);
+        for charge in charges {
+            simulation.add_charge(charge);
+        }
+        simulation.resolve_collisions();
+        let mut charges = simulation.charges;
+        charges.sort_by_key(|charge| charge.id);
+        charges
+    }
+
+    #[test]
+    fn collisions_do_not_depend_on_the_order_of_the_charges() {
+        let charges = cluster(&mut Simulation::new(200, 200));
+        let mut permuted = charges.clone();
+        permuted.reverse();
+        permuted.rotate_left(5);
+        let (expected, actual) = (resolved(charges), resolved(permuted));
+        assert!(expected.len() < 12, "no charges merged");
+        assert_eq!(expected.len(), actual.len());
+        for (expected, actual) in expected.iter().zip(&actual) {
+            assert_eq!(expected.id, actual.id);
+            assert_eq!(expected.q(), actual.q());
+            assert!((expected.center - actual.center).length() <= 1e-3, "{} != {}", expected.center, actual.center);
+            assert!((expected.velocity - actual.velocity).length() <= 1e-3, "{} != {}", expected.velocity, actual.velocity);
+        }
+    }
+
+    #[test]
+    fn energy_stays_bounded_when_charges_pass_through_each_other() {
+        let mut simulation = Simulation::new(400, 400);
+        simulation.damping = 0.0;
+        simulation.integrator = IntegratorKind::VelocityVerlet;
+        simulation.collisions = CollisionPolicy {
+            like_signs: CollisionKind::PassThrough,
+            opposite_signs: CollisionKind::PassThrough,
+            ..CollisionPolicy::default()
+        };
+        simulation.spawn_charge(Vec2::new(150.0, 200.0), PointCharge::DEFAULT_CHARGE, false);
+        simulation.spawn_charge(Vec2::new(250.0, 200.0), -PointCharge::DEFAULT_CHARGE, false);
+        let energy = |simulation: &Simulation| EnergyReport::measure(&simulation.charges, &simulation.units, 0.0, 0.0).total();
+        let initial_energy = energy(&simulation);
+
+        let mut closest_approach = f32::INFINITY;
+        let mut largest_error: f32 = 0.0;
+        for _ in 0..240 {
+            simulation.advance(1.0 / 60.0);
+            closest_approach = closest_approach.min(simulation.charges[0].center.distance(simulation.charges[1].center));
+            largest_error = largest_error.max((energy(&simulation) - initial_energy).abs());
+        }
+        assert!(closest_approach < 0.5 * Units::SOFTENING_DISTANCE, "charges only came {closest_approach} pixels close");
+        assert!(largest_error <= 0.05 * initial_energy.abs(), "energy drifted by {largest_error} from {initial_energy}");
+    }
+
+    #[test]
+    fn lower_quality_resamples_the_view_at_texel_centers() {
+        let mut simulation = Simulation::new(400, 300);
+        simulation.spawn_charge(Vec2::new(130.0, 170.0), PointCharge::DEFAULT_CHARGE, false);
+        simulation.update_potential();
+        simulation.set_resolution(Quality::Quarter.resolution());
+        assert_eq!(simulation.grid_size(), (100, 75));
+        assert_eq!(simulation.potentials.dim(), (100, 75));
+        assert!(simulation.update_potential());
+
+        let view = simulation.view();
+        assert_eq!(simulation.potentials[[0, 0]].0, view.point() + Vec2::splat(2.0));
+        assert_eq!(simulation.potentials[[99, 74]].0, view.point() + view.size() - Vec2::splat(2.0));
+        for (point, potential) in &simulation.potentials {
+            let expected = simulation.potential_at(*point);
+            assert!((potential - expected).abs() <= 1e-3 * expected.abs(), "{potential} != {expected} at {point}");
+        }
+    }
+
+    #[test]
+    fn field_arrows_are_hidden_under_charges_only() {
+        let mut simulation = Simulation::new(400, 400);
+        simulation.spawn_charge(Vec2::new(100.0, 100.0), PointCharge::DEFAULT_CHARGE, false);
+        simulation.spawn_charge(Vec2::new(300.0, 220.0), -4.0 * PointCharge::DEFAULT_CHARGE, false);
+        simulation.update_field();
+
+        let mut hidden = 0;
+        for test_charge in &simulation.test_charges {
+            let is_under_a_charge = simulation.charges.iter()
+                .any(|charge| test_charge.center.distance(charge.center) < 1.5 * charge.drawing_circle.radius);
+            assert_eq!(test_charge.is_hidden, is_under_a_charge, "arrow at {}", test_charge.center);
+            hidden += usize::from(test_charge.is_hidden);
+        }
+        assert!(hidden > 0);
+    }
+
+    #[test]
+    fn trajectories_do_not_depend_on_the_frame_rate() {
+        let run = |frame_time: f32| {
+            let mut simulation = Simulation::new(400, 400);
+            // Exact in binary, so that both frame rates run the same fixed steps
+            simulation.timestep.step = 1.0 / 128.0;
+            simulation.adaptive_step.max_steps_per_fixed_step = 8;
+            for (x, q, velocity) in [(100.0, 1.0, 2000.0), (300.0, -1.0, -2000.0)] {
+                let id = simulation.spawn_charge(Vec2::new(x, 200.0), q * PointCharge::DEFAULT_CHARGE, false);
+                simulation.charge_by_id_mut(id).unwrap().velocity = Vec2::new(velocity, 0.0);
+            }
+            for _ in 0..(0.5 / frame_time) as usize {
+                simulation.advance(frame_time);
+            }
+            (simulation.time(), simulation.charges.iter().map(|charge| (charge.center, charge.velocity)).collect::<Vec<_>>())
+        };
+        assert_eq!(run(1.0 / 64.0), run(1.0 / 16.0));
+    }
+
+    #[test]
+    fn merges_are_kept_for_the_last_step_only() {
+        let mut simulation = Simulation::new(400, 400);
+        simulation.spawn_charge(Vec2::new(200.0, 200.0), PointCharge::DEFAULT_CHARGE, false);
+        simulation.spawn_charge(Vec2::new(210.0, 200.0), -2.0 * PointCharge::DEFAULT_CHARGE, false);
+        simulation.step(simulation.timestep.substep());
+        simulation.step(simulation.timestep.substep());
+        assert!(simulation.take_merges().is_empty());
+
+        simulation.spawn_charge(Vec2::new(200.0, 200.0), PointCharge::DEFAULT_CHARGE, false);
+        simulation.advance(1.0 / 60.0);
+        assert_eq!(simulation.take_merges().len(), 1);
+        assert_eq!(simulation.charges.len(), 1);
+    }
+
+    #[test]
+    fn potential_grid_is_only_updated_for_changed_charges() {
+        let mut simulation = Simulation::new(100, 80);
+        let ids: Vec<usize> = (0..5)
+            .map(|i| simulation.spawn_charge(Vec2::new(10.0 + 20.0 * i as f32, 40.0), PointCharge::DEFAULT_CHARGE, false))
+            .collect();
+        assert!(simulation.update_potential());
+        assert!(!simulation.update_potential());
+
+        simulation.charge_by_id_mut(ids[2]).unwrap().set_center(Vec2::new(50.0, 60.0));
+        assert!(simulation.update_potential());
+        let incremental = simulation.potentials.clone();
+        simulation.potential_cache = None;
+        simulation.update_potential();
+        for ((_, expected), (_, actual)) in simulation.potentials.iter().zip(&incremental) {
+            assert!((expected - actual).abs() <= 1e-3 * expected.abs().max(1.0), "{actual} != {expected}");
+        }
+    }
+}
@@ -0,0 +1,177 @@
+use crate::charges::PointCharge;
+use macroquad::color::{Color, GOLD};
+use macroquad::math::Vec2;
+use std::fmt;
+
+/// A kind of particle: bundles the charge and mass every instance is spawned
+/// with, along with an optional color overriding the one derived from the charge.
+/// The built-in particles are scaled to screen size: only the ratios between
+/// their charges and masses are physical.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Species {
+    pub name: String,
+    pub charge: f32,
+    pub mass: f32,
+    pub color: Option<Color>,
+}
+
+impl Species {
+    const ELECTRON_TO_PROTON_MASS_RATIO: f32 = 1.0 / 1836.15;
+    const ALPHA_TO_PROTON_MASS_RATIO: f32 = 3.9726;
+
+    #[must_use]
+    pub fn new(name: &str, charge: f32, mass: f32, color: Option<Color>) -> Self {
+        Species { name: name.to_owned(), charge, mass, color }
+    }
+
+    /// A proton scaled up to `PointCharge::DEFAULT_CHARGE` and `PointCharge::DEFAULT_MASS`,
+    /// far from its real 1.602e-19 C and 1.673e-27 kg, so that it moves on screen.
+    #[must_use]
+    pub fn proton() -> Self {
+        Self::new("Proton (scaled)", PointCharge::DEFAULT_CHARGE, PointCharge::DEFAULT_MASS, None)
+    }
+
+    /// An electron scaled like `proton`: opposite charge, with the real mass ratio between them.
+    #[must_use]
+    pub fn electron() -> Self {
+        Self::new(
+            "Electron (scaled)",
+            -PointCharge::DEFAULT_CHARGE,
+            PointCharge::DEFAULT_MASS * Self::ELECTRON_TO_PROTON_MASS_RATIO,
+            None,
+        )
+    }
+
+    /// An alpha particle scaled like `proton`: twice its charge, with the real mass ratio between them.
+    #[must_use]
+    pub fn alpha_particle() -> Self {
+        Self::new(
+            "Alpha particle (scaled)",
+            2.0 * PointCharge::DEFAULT_CHARGE,
+            PointCharge::DEFAULT_MASS * Self::ALPHA_TO_PROTON_MASS_RATIO,
+            Some(GOLD),
+        )
+    }
+
+    /// User-editable species, starting out as a proton.
+    #[must_use]
+    pub fn custom() -> Self {
+        Self::new("Custom", PointCharge::DEFAULT_CHARGE, PointCharge::DEFAULT_MASS, None)
+    }
+
+    /// Same species with the opposite charge (e.g. a positron from an electron).
+    #[must_use]
+    pub fn antiparticle(&self) -> Self {
+        Species {
+            name: format!("Anti-{}", self.name.to_lowercase()),
+            charge: -self.charge,
+            ..self.clone()
+        }
+    }
+
+    #[must_use]
+    pub fn spawn(&self, id: usize, center: Vec2, is_fixed: bool) -> PointCharge {
+        let mut charge = PointCharge::new(id, center, self.charge, is_fixed);
+        charge.m = self.mass;
+        charge.set_color(self.color);
+        charge
+    }
+}
+
+impl fmt::Display for Species {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} (q = {:+.3e} C, m = {:.3e} kg)", self.name, self.charge, self.mass)
+    }
+}
+
+/// Ordered list of the species that can be spawned, with one of them selected.
+pub struct SpeciesRegistry {
+    species: Vec<Species>,
+    selected: usize,
+}
+
+impl Default for SpeciesRegistry {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SpeciesRegistry {
+    #[must_use]
+    pub fn new() -> Self {
+        SpeciesRegistry {
+            species: vec![Species::proton(), Species::electron(), Species::alpha_particle(), Species::custom()],
+            selected: 0,
+        }
+    }
+
+    /// Adds a species, replacing any existing one with the same name.
+    pub fn register(&mut self, species: Species) {
+        if let Some(existing) = self.species.iter_mut().find(|existing| existing.name == species.name) {
+            *existing = species;
+        } else {
+            self.species.push(species);
+        }
+    }
+
+    #[must_use]
+    pub fn get(&self, name: &str) -> Option<&Species> {
+        self.species.iter().find(|species| species.name == name)
+    }
+
+    #[must_use]
+    pub fn all(&self) -> &[Species] {
+        &self.species
+    }
+
+    #[must_use]
+    pub fn selected(&self) -> &Species {
+        &self.species[self.selected]
+    }
+
+    pub fn selected_mut(&mut self) -> &mut Species {
+        &mut self.species[self.selected]
+    }
+
+    /// Selects the species called `name`, returning whether it exists.
+    pub fn select(&mut self, name: &str) -> bool {
+        match self.species.iter().position(|species| species.name == name) {
+            Some(index) => {
+                self.selected = index;
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn select_next(&mut self) {
+        self.selected = (self.selected + 1) % self.species.len();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn spawned_color_survives_charge_changes() {
+        let species = Species::alpha_particle();
+        let mut charge = species.spawn(0, Vec2::ZERO, false);
+        charge.set_charge(3.0 * PointCharge::DEFAULT_CHARGE);
+        assert_eq!(charge.drawing_circle.color(), GOLD);
+        charge.flip_sign();
+        assert_eq!(charge.drawing_circle.color(), GOLD);
+        charge.set_color(None);
+        assert_ne!(charge.drawing_circle.color(), GOLD);
+    }
+
+    #[test]
+    fn scaled_species_keep_the_real_ratios() {
+        let (proton, electron, alpha) = (Species::proton(), Species::electron(), Species::alpha_particle());
+        assert_eq!(electron.charge, -proton.charge);
+        assert_eq!(alpha.charge, 2.0 * proton.charge);
+        assert!((proton.mass / electron.mass - 1836.15).abs() < 0.1);
+        assert!((alpha.mass / proton.mass - 3.9726).abs() < 1e-4);
+        assert!([proton, electron, alpha].iter().all(|species| species.name.ends_with("(scaled)")));
+    }
+}
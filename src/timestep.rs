@@ -0,0 +1,245 @@
+use crate::charges::PointCharge;
+use crate::collisions::closest_distance;
+use crate::units::Units;
+
+/// Turns variable frame times into a whole number of fixed-length physics
+/// steps, so the outcome of a scenario does not depend on the frame rate.
+#[derive(Clone, Debug, PartialEq)]
+pub struct FixedTimestep {
+    /// Simulated seconds advanced by each physics step.
+    pub step: f32,
+    /// Equal sub-steps each physics step is split into.
+    pub substeps: u32,
+    /// Simulated seconds per real second.
+    pub speed: f32,
+    /// Upper bound on the physics steps run for one rendered frame; time beyond it is dropped.
+    pub max_steps_per_frame: u32,
+    accumulator: f32,
+    /// Simulated seconds per real second over the last frame, below `speed` when
+    /// time was dropped.
+    reached_speed: f32,
+}
+
+impl Default for FixedTimestep {
+    fn default() -> Self {
+        Self::new(Self::DEFAULT_STEP, Self::DEFAULT_SUBSTEPS)
+    }
+}
+
+impl FixedTimestep {
+    pub const DEFAULT_STEP: f32 = 1.0 / 120.0;
+    pub const DEFAULT_SUBSTEPS: u32 = 2;
+    pub const MIN_SPEED: f32 = 1.0 / 16.0;
+    pub const MAX_SPEED: f32 = 64.0;
+    const DEFAULT_MAX_STEPS_PER_FRAME: u32 = 32;
+
+    #[must_use]
+    pub fn new(step: f32, substeps: u32) -> Self {
+        FixedTimestep {
+            step,
+            substeps: substeps.max(1),
+            speed: 1.0,
+            max_steps_per_frame: Self::DEFAULT_MAX_STEPS_PER_FRAME,
+            accumulator: 0.0,
+            reached_speed: 1.0,
+        }
+    }
+
+    /// Length of a single sub-step, in simulated seconds.
+    #[must_use]
+    pub fn substep(&self) -> f32 {
+        self.step / self.substeps.max(1) as f32
+    }
+
+    /// Simulated seconds per real second over the last frame. Falls short of
+    /// `speed` when more steps were due than `max_steps_per_frame` allows.
+    #[must_use]
+    pub fn reached_speed(&self) -> f32 {
+        self.reached_speed
+    }
+
+    /// Accounts for `frame_time` real seconds and returns how many physics steps
+    /// are now due.
+    pub fn accumulate(&mut self, frame_time: f32) -> u32 {
+        self.accumulator += frame_time * self.speed;
+        let mut steps = 0;
+        while self.accumulator >= self.step && steps < self.max_steps_per_frame {
+            self.accumulator -= self.step;
+            steps += 1;
+        }
+        let mut dropped = 0.0;
+        if steps == self.max_steps_per_frame {
+            // Too slow to keep up: drop the backlog instead of spiralling
+            dropped = (self.accumulator - self.step).max(0.0);
+            self.accumulator -= dropped;
+        }
+        if frame_time > 0.0 {
+            self.reached_speed = self.speed - dropped / frame_time;
+        }
+        steps
+    }
+
+    pub fn faster(&mut self) {
+        self.speed = (self.speed * 2.0).min(Self::MAX_SPEED);
+    }
+
+    pub fn slower(&mut self) {
+        self.speed = (self.speed / 2.0).max(Self::MIN_SPEED);
+    }
+}
+
+/// Shrinks the step during close encounters, when the 1/r² force changes too
+/// quickly for a fixed step, and lets it grow back gradually afterwards.
+#[derive(Clone, Debug, PartialEq)]
+pub struct AdaptiveStep {
+    pub enabled: bool,
+    /// Fraction of the smallest inter-particle distance a charge may cover in one step.
+    pub safety: f32,
+    /// Shortest step the controller may choose, in seconds.
+    pub min_step: f32,
+    /// Upper bound on the steps chosen within one fixed step, enforced as a
+    /// shortest step so that a close encounter cannot stall a frame.
+    pub max_steps_per_fixed_step: u32,
+    /// Largest factor by which the step may grow from one step to the next.
+    pub growth: f32,
+    last_step: Option<f32>,
+}
+
+impl Default for AdaptiveStep {
+    fn default() -> Self {
+        AdaptiveStep {
+            enabled: true,
+            safety: 0.05,
+            min_step: 1e-5,
+            max_steps_per_fixed_step: 512,
+            growth: 1.5,
+            last_step: None,
+        }
+    }
+}
+
+impl AdaptiveStep {
+    /// Step chosen last time, if any was chosen yet.
+    #[must_use]
+    pub fn last_step(&self) -> Option<f32> {
+        self.last_step
+    }
+
+    /// Longest step, at most `remaining`, that keeps every charge from moving more
+    /// than a `safety` fraction of the closest inter-particle distance, but no
+    /// shorter than allows `max_steps_per_fixed_step` steps in `fixed_step`.
+    pub fn next_step(&mut self, remaining: f32, fixed_step: f32, charges: &[PointCharge]) -> f32 {
+        let min_step = self.min_step.max(fixed_step / self.max_steps_per_fixed_step.max(1) as f32);
+        // Forces vary smoothly below the softening distance, so closer charges need no shorter step
+        let min_distance = closest_distance(charges).max(Units::SOFTENING_DISTANCE);
+        let max_speed = charges.iter().map(|charge| charge.velocity.length()).fold(0.0, f32::max);
+        let max_acceleration = charges.iter().map(|charge| charge.acceleration().length()).fold(0.0, f32::max);
+
+        let allowed_displacement = self.safety * min_distance;
+        let speed_limit = allowed_displacement / max_speed;
+        let acceleration_limit = (2.0 * allowed_displacement / max_acceleration).sqrt();
+        let mut step = speed_limit.min(acceleration_limit);
+        if let Some(last_step) = self.last_step {
+            step = step.min(last_step * self.growth);
+        }
+        step = step.max(min_step).min(remaining);
+        if remaining - step < min_step {
+            // Avoid leaving a sliver of time for a separate step
+            step = remaining;
+        }
+
+        self.last_step = Some(step);
+        step
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use macroquad::math::Vec2;
+
+    #[test]
+    fn speed_beyond_the_step_cap_is_reported() {
+        let mut timestep = FixedTimestep::default();
+        timestep.accumulate(1.0 / 60.0);
+        assert_eq!(timestep.reached_speed(), 1.0);
+
+        timestep.speed = FixedTimestep::MAX_SPEED;
+        let steps = timestep.accumulate(1.0 / 60.0);
+        assert_eq!(steps, timestep.max_steps_per_frame);
+        let capped_speed = steps as f32 * timestep.step * 60.0;
+        assert!((timestep.reached_speed() - capped_speed).abs() <= 1.0, "{} != {capped_speed}", timestep.reached_speed());
+    }
+
+    #[test]
+    fn adaptive_steps_stay_within_their_bounds() {
+        let mut charges = vec![
+            PointCharge::new(0, Vec2::new(0.0, 0.0), PointCharge::DEFAULT_CHARGE, false),
+            PointCharge::new(1, Vec2::new(100.0, 0.0), PointCharge::DEFAULT_CHARGE, false),
+        ];
+        let mut adaptive_step = AdaptiveStep::default();
+        let fixed_step = FixedTimestep::DEFAULT_STEP;
+        // At rest and far apart, the whole remaining time is taken at once
+        assert_eq!(adaptive_step.next_step(fixed_step, fixed_step, &charges), fixed_step);
+
+        // Fast charges move at most the safety fraction of their distance
+        charges[0].velocity = Vec2::new(1000.0, 0.0);
+        let step = adaptive_step.next_step(fixed_step, fixed_step, &charges);
+        assert!((step * 1000.0 - adaptive_step.safety * 100.0).abs() <= 1e-3, "{step}");
+
+        // But never take more than the budget of steps per fixed step
+        charges[0].velocity = Vec2::new(1e9, 0.0);
+        let shortest = fixed_step / adaptive_step.max_steps_per_fixed_step as f32;
+        assert!((adaptive_step.next_step(fixed_step, fixed_step, &charges) - shortest).abs() <= 1e-9);
+    }
+
+    #[test]
+    fn adaptive_steps_grow_back_gradually() {
+        let mut charges = vec![
+            PointCharge::new(0, Vec2::new(0.0, 0.0), PointCharge::DEFAULT_CHARGE, false),
+            PointCharge::new(1, Vec2::new(100.0, 0.0), PointCharge::DEFAULT_CHARGE, false),
+        ];
+        charges[0].velocity = Vec2::new(1000.0, 0.0);
+        let mut adaptive_step = AdaptiveStep::default();
+        let fixed_step = FixedTimestep::DEFAULT_STEP;
+        let short = adaptive_step.next_step(fixed_step, fixed_step, &charges);
+        charges[0].velocity = Vec2::ZERO;
+        let next = adaptive_step.next_step(fixed_step, fixed_step, &charges);
+        assert!((next - short * adaptive_step.growth).abs() <= 1e-9, "{next} != {}", short * adaptive_step.growth);
+    }
+
+    #[test]
+    fn adaptive_steps_add_up_to_the_fixed_step_without_slivers() {
+        let mut charges = vec![
+            PointCharge::new(0, Vec2::new(0.0, 0.0), PointCharge::DEFAULT_CHARGE, false),
+            PointCharge::new(1, Vec2::new(100.0, 0.0), PointCharge::DEFAULT_CHARGE, false),
+        ];
+        charges[0].velocity = Vec2::new(700.0, 0.0);
+        let mut adaptive_step = AdaptiveStep::default();
+        let fixed_step = FixedTimestep::DEFAULT_STEP;
+        let mut remaining = fixed_step;
+        let mut steps = vec![];
+        while remaining > 0.0 {
+            let step = adaptive_step.next_step(remaining, fixed_step, &charges);
+            assert!(step <= remaining);
+            steps.push(step);
+            remaining -= step;
+        }
+        assert!(steps.len() > 1);
+        assert!((steps.iter().sum::<f32>() - fixed_step).abs() <= 1e-6);
+        let min_step = adaptive_step.min_step.max(fixed_step / adaptive_step.max_steps_per_fixed_step as f32);
+        assert!(steps.iter().all(|&step| step >= min_step), "{steps:?}");
+    }
+
+    #[test]
+    fn overlapping_charges_do_not_shrink_the_step_below_the_softening_distance() {
+        let mut charges = vec![
+            PointCharge::new(0, Vec2::new(0.0, 0.0), PointCharge::DEFAULT_CHARGE, false),
+            PointCharge::new(1, Vec2::new(1.0, 0.0), PointCharge::DEFAULT_CHARGE, false),
+        ];
+        charges[0].velocity = Vec2::new(100.0, 0.0);
+        let mut adaptive_step = AdaptiveStep::default();
+        let step = adaptive_step.next_step(1.0, 1.0, &charges);
+        assert!((step * 100.0 - adaptive_step.safety * Units::SOFTENING_DISTANCE).abs() <= 1e-4, "{step}");
+    }
+}
@@ -0,0 +1,191 @@
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum UnitMode {
+    /// Pixels act as metres and forces are scaled up so that motion "feels nice".
+    Tuned,
+    /// True Coulomb constant, with distances converted through `pixels_per_metre`.
+    Si,
+}
+
+/// Unit system used to turn on-screen distances and charges into forces and
+/// potentials. Any scaling meant only for display is exposed separately and
+/// must be applied at draw time.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Units {
+    pub mode: UnitMode,
+    pub pixels_per_metre: f32,
+}
+
+impl Default for Units {
+    fn default() -> Self {
+        Self::tuned()
+    }
+}
+
+impl Units {
+    pub const SI_COULOMB_CONSTANT: f32 = 8.987_552e9;
+    pub const DEFAULT_PIXELS_PER_METRE: f32 = 100.0;
+    // Historical tuning, 8.99e10 rather than 8.99e9, kept so that Tuned mode behaves as it always did
+    const TUNED_COULOMB_CONSTANT: f32 = 8.99 * 10e9;
+    // Originally 50e5, applied once per frame: scaled by 60 FPS now that accelerations are integrated over time
+    const TUNED_FORCE_SCALING_FACTOR: f32 = 3e8;
+    // Full-scale potential of the heatmap in Tuned mode
+    const TUNED_FULL_SCALE_POTENTIAL: f32 = 100.0;
+    /// Distance in pixels below which charges act as uniformly charged balls of
+    /// that radius, keeping forces and potentials finite when they overlap.
+    /// Shorter than any two charge circles touching, so that the forces between
+    /// charges that do not overlap are exact.
+    pub const SOFTENING_DISTANCE: f32 = 16.0;
+
+    #[must_use]
+    pub fn tuned() -> Self {
+        Units { mode: UnitMode::Tuned, pixels_per_metre: 1.0 }
+    }
+
+    #[must_use]
+    pub fn si(pixels_per_metre: f32) -> Self {
+        Units { mode: UnitMode::Si, pixels_per_metre }
+    }
+
+    /// Switches between Tuned and SI (at the default scale).
+    #[must_use]
+    pub fn toggled(&self) -> Self {
+        match self.mode {
+            UnitMode::Tuned => Self::si(Self::DEFAULT_PIXELS_PER_METRE),
+            UnitMode::Si => Self::tuned(),
+        }
+    }
+
+    #[must_use]
+    pub fn coulomb_constant(&self) -> f32 {
+        match self.mode {
+            UnitMode::Tuned => Self::TUNED_COULOMB_CONSTANT,
+            UnitMode::Si => Self::SI_COULOMB_CONSTANT,
+        }
+    }
+
+    fn force_scaling_factor(&self) -> f32 {
+        match self.mode {
+            UnitMode::Tuned => Self::TUNED_FORCE_SCALING_FACTOR,
+            UnitMode::Si => 1.0,
+        }
+    }
+
+    #[must_use]
+    pub fn pixels_to_metres(&self, pixels: f32) -> f32 {
+        pixels / self.pixels_per_metre
+    }
+
+    #[must_use]
+    pub fn metres_to_pixels(&self, metres: f32) -> f32 {
+        metres * self.pixels_per_metre
+    }
+
+    /// Magnitude of the Coulomb force between `q1` and `q2` lying `distance_squared`
+    /// square pixels apart; positive when repulsive. Falls linearly to zero
+    /// below `SOFTENING_DISTANCE`.
+    #[must_use]
+    pub fn coulomb_force(&self, q1: f32, q2: f32, distance_squared: f32) -> f32 {
+        let distance_squared = if distance_squared < Self::SOFTENING_DISTANCE.powi(2) {
+            // The same force as 1 / r², with r replaced so that it is proportional to r
+            Self::SOFTENING_DISTANCE.powi(3) / distance_squared.sqrt()
+        } else {
+            distance_squared
+        };
+        let distance_squared = distance_squared / self.pixels_per_metre.powi(2);
+        self.force_scaling_factor() * self.coulomb_constant() * q1 * q2 / distance_squared
+    }
+
+    /// Potential generated by `q` at `distance` pixels from it, in volts,
+    /// consistent with `coulomb_force` below `SOFTENING_DISTANCE`.
+    #[must_use]
+    pub fn potential(&self, q: f32, distance: f32) -> f32 {
+        let softening_distance = self.pixels_to_metres(Self::SOFTENING_DISTANCE);
+        let distance = self.pixels_to_metres(distance);
+        if distance < softening_distance {
+            self.coulomb_constant() * q * (3.0 - (distance / softening_distance).powi(2)) / (2.0 * softening_distance)
+        } else {
+            self.coulomb_constant() * q / distance
+        }
+    }
+
+    /// Electrostatic potential energy of `q1` and `q2` lying `distance` pixels
+    /// apart, consistent with `coulomb_force` so that total energy is conserved.
+    #[must_use]
+    pub fn potential_energy(&self, q1: f32, q2: f32, distance: f32) -> f32 {
+        self.energy_in_potential(q1, self.potential(q2, distance))
+    }
+
+    /// Potential energy of `q` where the potential is `potential` volts,
+    /// consistent with `potential_energy`.
+    #[must_use]
+    pub fn energy_in_potential(&self, q: f32, potential: f32) -> f32 {
+        self.force_scaling_factor() * q * potential
+    }
+
+    /// Unit of field strengths, as computed from `coulomb_force` on a unit charge.
+    /// Tuned forces are scaled up, so their fields have no physical unit.
+    #[must_use]
+    pub fn field_unit(&self) -> &'static str {
+        match self.mode {
+            UnitMode::Tuned => "arb. u.",
+            UnitMode::Si => "N/C",
+        }
+    }
+
+    /// Potential shown at full color intensity by the heatmap. Only meant for
+    /// drawing: it keeps the picture comparable across unit modes.
+    #[must_use]
+    pub fn full_scale_potential(&self) -> f32 {
+        match self.mode {
+            UnitMode::Tuned => Self::TUNED_FULL_SCALE_POTENTIAL,
+            // Same charge at the same on-screen distance gives the same color as in Tuned mode
+            UnitMode::Si => Self::TUNED_FULL_SCALE_POTENTIAL * self.pixels_per_metre
+                * Self::SI_COULOMB_CONSTANT / Self::TUNED_COULOMB_CONSTANT,
+        }
+    }
+}
+
+impl fmt::Display for Units {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.mode {
+            UnitMode::Tuned => write!(f, "Tuned"),
+            UnitMode::Si => write!(f, "SI ({} px/m)", self.pixels_per_metre),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MICROCOULOMB: f32 = 1e-6;
+
+    fn assert_close(actual: f32, expected: f32) {
+        assert!((actual - expected).abs() <= 1e-3 * expected.abs(), "{actual} != {expected}");
+    }
+
+    #[test]
+    fn si_mode_matches_coulombs_law() {
+        // Two 1 µC charges 1 m apart, at two different scales
+        for pixels_per_metre in [Units::DEFAULT_PIXELS_PER_METRE, 250.0] {
+            let units = Units::si(pixels_per_metre);
+            let distance = units.metres_to_pixels(1.0);
+            assert_close(units.coulomb_force(MICROCOULOMB, MICROCOULOMB, distance.powi(2)), 8.988e-3);
+            assert_close(units.coulomb_force(MICROCOULOMB, -MICROCOULOMB, distance.powi(2)), -8.988e-3);
+            assert_close(units.potential(MICROCOULOMB, distance), 8.988e3);
+            assert_close(units.potential_energy(MICROCOULOMB, MICROCOULOMB, distance), 8.988e-3);
+        }
+    }
+
+    #[test]
+    fn si_force_falls_with_the_square_of_the_distance() {
+        let units = Units::si(Units::DEFAULT_PIXELS_PER_METRE);
+        let near = units.coulomb_force(MICROCOULOMB, MICROCOULOMB, units.metres_to_pixels(0.5).powi(2));
+        let far = units.coulomb_force(MICROCOULOMB, MICROCOULOMB, units.metres_to_pixels(2.0).powi(2));
+        assert_close(near, 4.0 * 8.988e-3);
+        assert_close(far, 8.988e-3 / 4.0);
+    }
+}
@@ -1,8 +1,9 @@
-use macroquad::color::{GREEN, RED, WHITE};
+use macroquad::color::{GREEN, WHITE};
 use macroquad::math::{Rect, Vec2};
-use macroquad::shapes::{draw_circle, draw_circle_lines, draw_line, draw_rectangle_lines};
-use macroquad::text::{draw_text, draw_text_ex, get_text_center, TextParams};
+use macroquad::shapes::{draw_circle_lines, draw_line, draw_rectangle_lines};
+use macroquad::text::{draw_text_ex, get_text_center, TextParams};
 use crate::charges::{calculate_potential, PointCharge};
+use crate::units::Units;
 
 pub struct Voltmeter {
     reticle_center: Vec2,
@@ -25,8 +26,6 @@ impl Voltmeter {
     const RETICLE_RADIUS: f32 = 24.0;
     const RECTANGLE_VERTICAL_OFFSET: f32 = Self::RETICLE_RADIUS + 10.0;
     const RECTANGLE_HORIZONTAL_OFFSET: f32 = 2.0*Self::RETICLE_RADIUS;
-    const HORIZONTAL_TEXT_OFFSET: f32 = Self::RETICLE_RADIUS / 4.0;
-    const VERTICAL_TEXT_OFFSET: f32 = 25.0;
     const MEASURE_FONT_SIZE: u16 = 24;
     #[must_use]
     pub fn new() -> Self {
@@ -38,8 +37,8 @@ impl Voltmeter {
             h: Self::RETICLE_RADIUS * 2.0,
         };
         Voltmeter {
-            reticle_center: reticle_center,
-            rectangle: rectangle,
+            reticle_center,
+            rectangle,
             measured_potential: 0.0,
             is_active: false,
             equipotentials: vec![]
@@ -54,9 +53,11 @@ impl Voltmeter {
             h: Self::RETICLE_RADIUS * 2.0,
         };
     }
-    pub fn update(&mut self, new_position: Vec2, charges: &Vec<PointCharge>) {
+    /// Moves the reticle to `new_position`, in screen coordinates, and measures
+    /// the potential at `probe`, the world point under it.
+    pub fn update(&mut self, new_position: Vec2, probe: Vec2, charges: &[PointCharge], units: &Units) {
         self.movement(new_position);
-        self.measured_potential = calculate_potential(&self.reticle_center, charges);
+        self.measured_potential = calculate_potential(&probe, charges, units);
     }
 
     pub fn add_equipotential(&mut self) {
@@ -88,4 +89,38 @@ impl Voltmeter {
                           },);
         }
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn probe_measures_at_the_world_point_not_the_screen_one() {
+        let charges = [PointCharge::new(0, Vec2::new(500.0, 500.0), PointCharge::DEFAULT_CHARGE, true)];
+        let units = Units::default();
+        let mut voltmeter = Voltmeter::new();
+        let (screen, world) = (Vec2::new(20.0, 30.0), Vec2::new(540.0, 530.0));
+        voltmeter.update(screen, world, &charges, &units);
+
+        assert_eq!(voltmeter.reticle_center, screen);
+        assert_eq!(voltmeter.measured_potential, units.potential(PointCharge::DEFAULT_CHARGE, 50.0));
+        assert_ne!(voltmeter.measured_potential, calculate_potential(&screen, &charges, &units));
+    }
+
+    #[test]
+    fn equipotentials_take_the_measured_potential() {
+        let charges = [PointCharge::new(0, Vec2::ZERO, -PointCharge::DEFAULT_CHARGE, true)];
+        let units = Units::default();
+        let mut voltmeter = Voltmeter::new();
+        for probe in [Vec2::new(100.0, 0.0), Vec2::new(0.0, 200.0)] {
+            voltmeter.update(Vec2::ZERO, probe, &charges, &units);
+            voltmeter.add_equipotential();
+        }
+        assert_eq!(voltmeter.equipotentials, [
+            units.potential(-PointCharge::DEFAULT_CHARGE, 100.0),
+            units.potential(-PointCharge::DEFAULT_CHARGE, 200.0),
+        ]);
+        voltmeter.clear_equipotentials();
+        assert!(voltmeter.equipotentials.is_empty());
+    }
+}
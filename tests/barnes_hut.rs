@@ -0,0 +1,140 @@
+use macroquad::math::Vec2;
+use point_charge_simulation::barnes_hut::QuadTree;
+use point_charge_simulation::charges::{coulomb_force, PointCharge};
+use point_charge_simulation::simulation::Simulation;
+use point_charge_simulation::units::Units;
+
+/// Deterministic pseudo-random charges of either sign over a 1000 × 1000 pixels square.
+fn random_sources(count: usize, seed: u64) -> Vec<(Vec2, f32)> {
+    let mut state = seed;
+    let mut next = move || {
+        // Numerical Recipes' linear congruential generator
+        state = state.wrapping_mul(6_364_136_223_846_793_005).wrapping_add(1_442_695_040_888_963_407);
+        (state >> 40) as f32 / (1u64 << 24) as f32
+    };
+    (0..count)
+        .map(|_| {
+            let point = Vec2::new(1000.0 * next(), 1000.0 * next());
+            let q = if next() < 0.5 { -1.0 } else { 1.0 } * (0.5 + 2.0 * next()) * PointCharge::DEFAULT_CHARGE;
+            (point, q)
+        })
+        .collect()
+}
+
+fn direct_field(sources: &[(Vec2, f32)], point: Vec2, units: &Units, exclude: Option<usize>) -> Vec2 {
+    sources.iter().enumerate()
+        .filter(|(i, _)| Some(*i) != exclude)
+        .map(|(_, (center, q))| coulomb_force(point, 1.0, *center, *q, units))
+        .sum()
+}
+
+fn direct_potential(sources: &[(Vec2, f32)], point: Vec2, units: &Units) -> f32 {
+    sources.iter().map(|(center, q)| units.potential(*q, center.distance(point))).sum()
+}
+
+/// Root mean square of `errors` relative to that of `exact`.
+fn relative_rms_error(errors: &[f32], exact: &[f32]) -> f32 {
+    let rms = |values: &[f32]| (values.iter().map(|value| value * value).sum::<f32>() / values.len() as f32).sqrt();
+    rms(errors) / rms(exact)
+}
+
+#[test]
+fn zero_opening_angle_matches_the_direct_sum() {
+    let units = Units::default();
+    let sources = random_sources(500, 1);
+    let tree = QuadTree::new(sources.iter().copied(), 0.0);
+    for (i, (point, _q)) in sources.iter().enumerate().take(50) {
+        let exact = direct_field(&sources, *point, &units, Some(i));
+        let approximate = tree.field_at(*point, &units, Some(i));
+        assert!((approximate - exact).length() <= 1e-4 * exact.length(), "{approximate} != {exact}");
+    }
+}
+
+#[test]
+fn forces_between_charges_are_accurate() {
+    let units = Units::default();
+    let sources = random_sources(2000, 2);
+    let tree = QuadTree::new(sources.iter().copied(), QuadTree::DEFAULT_OPENING_ANGLE);
+    let (errors, exact): (Vec<f32>, Vec<f32>) = sources.iter().enumerate().step_by(10)
+        .map(|(i, (point, _q))| {
+            let exact = direct_field(&sources, *point, &units, Some(i));
+            ((tree.field_at(*point, &units, Some(i)) - exact).length(), exact.length())
+        })
+        .unzip();
+    let error = relative_rms_error(&errors, &exact);
+    assert!(error < 0.01, "relative error {error}");
+}
+
+#[test]
+fn field_and_potential_away_from_charges_are_accurate() {
+    let units = Units::default();
+    let sources = random_sources(2000, 3);
+    let tree = QuadTree::new(sources.iter().copied(), QuadTree::DEFAULT_OPENING_ANGLE);
+    let points: Vec<Vec2> = (0..400).map(|i| Vec2::new(12.5 + 50.0 * (i % 20) as f32, 12.5 + 50.0 * (i / 20) as f32)).collect();
+
+    let (field_errors, fields): (Vec<f32>, Vec<f32>) = points.iter()
+        .map(|point| {
+            let exact = direct_field(&sources, *point, &units, None);
+            ((tree.field_at(*point, &units, None) - exact).length(), exact.length())
+        })
+        .unzip();
+    let field_error = relative_rms_error(&field_errors, &fields);
+    assert!(field_error < 0.01, "relative field error {field_error}");
+
+    let (potential_errors, potentials): (Vec<f32>, Vec<f32>) = points.iter()
+        .map(|point| {
+            let exact = direct_potential(&sources, *point, &units);
+            (tree.potential_at(*point, &units, None, 0.0) - exact, exact)
+        })
+        .unzip();
+    let potential_error = relative_rms_error(&potential_errors, &potentials);
+    assert!(potential_error < 0.02, "relative potential error {potential_error}");
+}
+
+#[test]
+fn error_shrinks_with_the_opening_angle() {
+    let units = Units::default();
+    let sources = random_sources(1000, 4);
+    let error = |opening_angle: f32| {
+        let tree = QuadTree::new(sources.iter().copied(), opening_angle);
+        let (errors, exact): (Vec<f32>, Vec<f32>) = sources.iter().enumerate().step_by(5)
+            .map(|(i, (point, _q))| {
+                let exact = direct_field(&sources, *point, &units, Some(i));
+                ((tree.field_at(*point, &units, Some(i)) - exact).length(), exact.length())
+            })
+            .unzip();
+        relative_rms_error(&errors, &exact)
+    };
+    let (coarse, fine) = (error(1.0), error(0.3));
+    assert!(fine < coarse, "{fine} >= {coarse}");
+}
+
+#[test]
+fn neutral_sources_are_ignored() {
+    let units = Units::default();
+    let mut sources = random_sources(200, 5);
+    let charged = QuadTree::new(sources.iter().copied(), QuadTree::DEFAULT_OPENING_ANGLE);
+    sources.extend(random_sources(200, 6).into_iter().map(|(point, _q)| (point, 0.0)));
+    let with_neutrals = QuadTree::new(sources.iter().copied(), QuadTree::DEFAULT_OPENING_ANGLE);
+    let point = Vec2::new(500.0, 500.0);
+    assert_eq!(charged.field_at(point, &units, None), with_neutrals.field_at(point, &units, None));
+}
+
+#[test]
+fn simulation_forces_match_the_direct_sum_in_crowded_scenes() {
+    let units = Units::default();
+    let sources = random_sources(4 * Simulation::BARNES_HUT_MIN_CHARGES, 7);
+    let mut simulation = Simulation::new(1000, 1000);
+    for (point, q) in &sources {
+        simulation.spawn_charge(*point, *q, false);
+    }
+    simulation.update_forces();
+    let (errors, exact): (Vec<f32>, Vec<f32>) = simulation.charges.iter().enumerate()
+        .map(|(i, charge)| {
+            let exact = charge.q() * direct_field(&sources, charge.center, &units, Some(i));
+            ((charge.net_force() - exact).length(), exact.length())
+        })
+        .unzip();
+    let error = relative_rms_error(&errors, &exact);
+    assert!(error < 0.01, "relative error {error}");
+}